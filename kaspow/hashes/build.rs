@@ -6,6 +6,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
     let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    // the keccak crate only
+    if env::var_os("CARGO_FEATURE_NO_ASM").is_some() {
+        return Ok(());
+    }
     if target_arch == "x86_64" && target_os != "windows" && target_os != "macos" {
         cc::Build::new().flag("-c").file("src/keccakf1600_x86-64.s").compile("libkeccak.a");
     }
@@ -59,20 +59,29 @@ impl KHeavyHash {
     }
 }
 
+// the asm is selected at runtime where it's built, the keccak crate is the portable fallback
 pub mod keccak256 {
-    #[cfg(any(not(target_arch = "x86_64"), feature = "no-asm", target_os = "windows"))]
-    #[inline(always)]
-    pub fn f1600(state: &mut [u64; 25]) {
-        keccak::f1600(state);
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    pub const ASM: bool = cfg!(all(target_arch = "x86_64", not(feature = "no-asm"), not(target_os = "windows")));
+
+    static USE_ASM: AtomicBool = AtomicBool::new(ASM);
+
+    pub fn use_asm(asm: bool) {
+        USE_ASM.store(asm && ASM, Ordering::Relaxed);
     }
 
-    #[cfg(all(target_arch = "x86_64", not(feature = "no-asm"), not(target_os = "windows")))]
     #[inline(always)]
     pub fn f1600(state: &mut [u64; 25]) {
-        extern "C" {
-            fn KeccakF1600(state: &mut [u64; 25]);
+        #[cfg(all(target_arch = "x86_64", not(feature = "no-asm"), not(target_os = "windows")))]
+        if USE_ASM.load(Ordering::Relaxed) {
+            extern "C" {
+                fn KeccakF1600(state: &mut [u64; 25]);
+            }
+            unsafe { KeccakF1600(state) };
+            return;
         }
-        unsafe { KeccakF1600(state) }
+        keccak::f1600(state);
     }
 }
 
@@ -0,0 +1,156 @@
+// the block header of kaspad, only the fields hashed by the pow
+use hashes::{BlockHash, Hash, HasherBase};
+
+#[derive(Debug, Clone, Default)]
+pub struct Header {
+    pub version: u16,
+    pub parents_by_level: Vec<Vec<Hash>>,
+    pub hash_merkle_root: Hash,
+    pub accepted_id_merkle_root: Hash,
+    pub utxo_commitment: Hash,
+    /// Timestamp is in milliseconds
+    pub timestamp: u64,
+    pub bits: u32,
+    pub nonce: u64,
+    pub daa_score: u64,
+    /// The big-endian bytes of the Uint192 blue work
+    pub blue_work: Vec<u8>,
+    pub blue_score: u64,
+    pub pruning_point: Hash,
+}
+
+impl Header {
+    #[inline]
+    pub fn hash(&self) -> Hash {
+        hash_override_nonce_time(self, self.nonce, self.timestamp)
+    }
+}
+
+trait HasherExtensions {
+    fn write_len(&mut self, len: usize) -> &mut Self;
+    fn write_var_bytes(&mut self, bytes: &[u8]) -> &mut Self;
+    fn write_var_array(&mut self, hashes: &[Hash]) -> &mut Self;
+    fn write_blue_work(&mut self, work: &[u8]) -> &mut Self;
+}
+
+impl<T: HasherBase> HasherExtensions for T {
+    #[inline(always)]
+    fn write_len(&mut self, len: usize) -> &mut Self {
+        self.update((len as u64).to_le_bytes())
+    }
+
+    #[inline(always)]
+    fn write_var_bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.write_len(bytes.len()).update(bytes)
+    }
+
+    #[inline(always)]
+    fn write_var_array(&mut self, hashes: &[Hash]) -> &mut Self {
+        self.write_len(hashes.len());
+        hashes.iter().fold(self, |hasher, hash| hasher.update(hash))
+    }
+
+    #[inline(always)]
+    fn write_blue_work(&mut self, work: &[u8]) -> &mut Self {
+        // the leading zero bytes are trimmed
+        let start = work.iter().position(|byte| *byte != 0).unwrap_or(work.len());
+        self.write_var_bytes(&work[start..])
+    }
+}
+
+/// Returns the header hash with the nonce and the timestamp overridden,
+/// the pre pow hash is the one with both of them zeroed.
+#[inline]
+pub fn hash_override_nonce_time(header: &Header, nonce: u64, timestamp: u64) -> Hash {
+    let mut hasher = BlockHash::new();
+    hasher.update(header.version.to_le_bytes()).write_len(header.parents_by_level.len()); // Write the number of parent levels
+
+    // Write parents at each level
+    header.parents_by_level.iter().for_each(|level| {
+        hasher.write_var_array(level);
+    });
+
+    // Write all header fields
+    hasher
+        .update(header.hash_merkle_root)
+        .update(header.accepted_id_merkle_root)
+        .update(header.utxo_commitment)
+        .update(timestamp.to_le_bytes())
+        .update(header.bits.to_le_bytes())
+        .update(nonce.to_le_bytes())
+        .update(header.daa_score.to_le_bytes())
+        .update(header.blue_score.to_le_bytes())
+        .write_blue_work(&header.blue_work)
+        .update(header.pruning_point);
+
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_hashing() {
+        let mut header = Header {
+            version: 1,
+            parents_by_level: vec![vec![1.into()]],
+            timestamp: 234,
+            bits: 23,
+            nonce: 567,
+            blue_work: vec![0, 0, 1],
+            ..Default::default()
+        };
+        let hash = header.hash();
+        assert_ne!(hash, Hash::default());
+        assert_eq!(hash, hash_override_nonce_time(&header, 567, 234));
+        assert_ne!(hash, hash_override_nonce_time(&header, 0, 0));
+
+        // the leading zeros of blue work aren't hashed
+        header.blue_work = vec![1];
+        assert_eq!(hash, header.hash());
+
+        header.nonce = 0;
+        header.timestamp = 0;
+        assert_eq!(header.hash(), hash_override_nonce_time(&header, 0, 0));
+    }
+
+    #[test]
+    fn test_genesis_hashes() {
+        let hash = |h: &str| h.parse::<Hash>().unwrap();
+        // the genesis blocks of mainnet and testnet, from consensus/core/src/config/genesis.rs of rusty-kaspa 0.13.4
+        let genesis = [
+            (
+                1637609671037,
+                486722099,
+                211244,
+                1312860,
+                "8ec898568c6801d13df4ee6e2a1b54b7e6236f671f20954f05306410518eeb32",
+                "710f27df423e63aa6cdb72b89ea5a06cffa399d66f167704455b5af59def8e20",
+                "58c2d4199e21f910d1571d114969cecef48f09f934d42ccb6a281a15868f2999",
+            ),
+            (
+                1633687894966,
+                511705087,
+                83330,
+                0,
+                "17341408a5724556504df4d6cf515cbfbb220430dc451c743c22d5e911720c2a",
+                "544eb3142c000f0ad2c76ac41f4222abbababed830eeafee4b6dc56b52d5cac0",
+                "f896a3034873be1739fc4359236899fd3d65d2bc94f9780df0d0da3eb1cc4370",
+            ),
+        ];
+        for (timestamp, bits, nonce, daa_score, merkle_root, utxo_commitment, block_hash) in genesis {
+            let header = Header {
+                hash_merkle_root: hash(merkle_root),
+                utxo_commitment: hash(utxo_commitment),
+                timestamp,
+                bits,
+                nonce,
+                daa_score,
+                blue_work: vec![0; 24],
+                ..Default::default()
+            };
+            assert_eq!(header.hash(), hash(block_hash));
+        }
+    }
+}
@@ -1,17 +1,19 @@
 #[macro_use]
 extern crate anyhow;
 
+pub mod header;
 // public for benchmarks
 #[doc(hidden)]
 pub mod matrix;
 #[doc(hidden)]
 pub mod xoshiro;
 
+use crate::header::{hash_override_nonce_time, Header};
 use crate::matrix::Matrix;
 use math::Uint320;
 use std::mem;
 
-pub use hashes::{Hash, PowHash};
+pub use hashes::{keccak256, Hash, PowHash};
 pub use math::Uint256;
 
 pub fn target2difficulty(target: &Uint256) -> Uint256 {
@@ -40,17 +42,17 @@ pub struct State {
 }
 
 impl State {
-    // #[inline]
-    // pub fn new(header: &Header) -> Self {
-    //     let target = Uint256::from_compact_target_bits(header.bits);
-    //     // Zero out the time and nonce.
-    //     let pre_pow_hash = hashing::header::hash_override_nonce_time(header, 0, 0);
-    //     // PRE_POW_HASH || TIME || 32 zero byte padding || NONCE
-    //     let hasher = PowHash::new(pre_pow_hash, header.timestamp);
-    //     let matrix = Matrix::generate(pre_pow_hash);
-
-    //     Self { matrix, target, hasher }
-    // }
+    #[inline]
+    pub fn new(header: &Header) -> Self {
+        let target = Uint256::from_compact_target_bits(header.bits);
+        // Zero out the time and nonce.
+        let pre_pow_hash = hash_override_nonce_time(header, 0, 0);
+        // PRE_POW_HASH || TIME || 32 zero byte padding || NONCE
+        let hasher = PowHash::new(pre_pow_hash, header.timestamp);
+        let matrix = Matrix::generate(pre_pow_hash);
+
+        Self { matrix, target, hasher }
+    }
     pub fn with_powhash(powhash: &str, diff: u64) -> anyhow::Result<Self> {
         const SIZE: usize = mem::size_of::<Hash>() * 2;
 
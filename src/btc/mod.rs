@@ -1,30 +1,42 @@
 use bitcoin::util::uint::Uint256;
-use futures::future::Either;
-use serde_json;
-use std::mem;
+use bitcoin_hashes::{sha256d::Hash, Hash as HashTrait};
+use std::{mem, time::Duration};
 
 pub mod pow;
 pub mod proto;
+pub mod sha256;
 
-use crate::config::TIMEOUT_SECS;
-use crate::state::{Handle, Handler, Job as JobID, Req, Run, State, Worker};
-use crate::util;
+use crate::config::{Config, SHARES_TIMEOUT_SECS, TIMEOUT_SECS};
+use crate::state::{Handle, Handler, Job as JobID, Req, Run, State, Statev, Worker};
+use crate::stratum::{self, Message, ReconnectError, Response};
+use crate::sv2;
+use crate::util::{self, LoginError};
 
-use pow::{target_to_difficulty, unit_target, Computer};
-use proto::{make_login, make_submit, Job, MethodForm, ResultForm, METHOD_SUBMIT_WORK};
+use pow::{target_to_difficulty, target_uint256_from_hashraw, unit_target, Computer};
+use proto::{
+    make_login, make_open_channel, make_setup_connection, make_submit, make_template, parse_notify, parse_response, parse_template, parse_u256, Channel, FormTemplate, Job, MethodParams,
+    ResponseParams, METHOD_AUTHORIZE, METHOD_GET_TEMPLATE, METHOD_OPEN_CHANNEL, METHOD_SETUP_CONNECTION, METHOD_SUBMIT_BLOCK, METHOD_SUBMIT_SHARES, METHOD_SUBMIT_WORK, METHOD_SUBSCRIBE,
+    TEMPLATE_NONCE2_BYTES,
+};
 
 #[derive(Debug, Clone)]
 pub enum BtcJob {
-    Nonce1t((String, usize, u128, Uint256)),
-    Compute(Job),
+    // (nonce1, nonce2_bytes, nonce2_max, target, version_mask)
+    Nonce1t((String, usize, u128, Uint256, u32)),
+    Compute(Box<Job>),
+    // the standard channel of stratum v2 with its active job
+    Channel(Box<Channel>),
     Sleep,
     Exit,
 }
 
 impl JobID for BtcJob {
+    type Shared = ();
+    fn shared(_: &Config) -> Self::Shared {}
     fn jobid(&self) -> String {
         match &self {
             Self::Compute(job) => job.jobid.clone(),
+            Self::Channel(c) => c.job.as_ref().map(|j| j.jobid.clone()).unwrap_or_else(|| "0".to_owned()),
             _ => "0".to_owned(),
         }
     }
@@ -36,17 +48,249 @@ impl Default for BtcJob {
     }
 }
 
+// the template of the same transactions is renewed after so many secs for its curtime
+const TEMPLATE_RENEW_SECS: u32 = 30;
+
+impl State<BtcJob> {
+    // the job of getblocktemplate, the coinbase is built by cminer
+    fn handle_template(&self, rf: &Response) -> util::Result<()> {
+        let form = rf.result::<FormTemplate>();
+        let mut lock = self.value().lock();
+        let lock = &mut *lock;
+        let req = lock.reqs.remove(rf.id);
+        let form = match form {
+            Ok(form) => form,
+            Err(e) => {
+                error!("{} failed: {}, error: {:?}", METHOD_GET_TEMPLATE, e, rf.error_string());
+                return Ok(());
+            }
+        };
+
+        let payout = self.config().payout.as_deref().expect("payout checked by config").parse::<bitcoin::Address>()?;
+        let mut j = parse_template(&form, &payout.script_pubkey())?;
+        match &lock.job {
+            BtcJob::Compute(oj) if oj.prev_hash == j.prev_hash && oj.merkle_branches == j.merkle_branches && j.ntime < oj.ntime + TEMPLATE_RENEW_SECS => return Ok(()),
+            BtcJob::Exit => return Ok(()),
+            _ => {}
+        }
+
+        use rand::{thread_rng, Rng};
+        j.id = lock.jobsc.get() + 1;
+        j.nonce2_bytes = TEMPLATE_NONCE2_BYTES;
+        j.nonce2_max = 2u128.pow(8 * TEMPLATE_NONCE2_BYTES as u32);
+        j.nonce2 = thread_rng().gen_range(0, j.nonce2_max / 2);
+        info!(
+            "template: {}, height: {}, diff: {}, prevhash: {}, txs: {}, coinbasevalue: {}, nbits: {:08x}, costed: {:?}",
+            j.jobid,
+            form.height,
+            target_to_difficulty(&j.target),
+            j.prev_hash,
+            form.transactions.len(),
+            form.coinbasevalue,
+            j.nbits,
+            req.map(|r| r.time.elapsed())
+        );
+        lock.job = BtcJob::Compute(Box::new(j));
+        lock.jobsc.add_slow(1);
+        Ok(())
+    }
+    // null is accepted, or the reason of rejected
+    fn handle_submit_block(&self, rf: &Response) {
+        let mut lock = self.value().lock();
+        let lock = &mut *lock;
+        let costed = lock.reqs.remove(rf.id).map(|r| r.time.elapsed());
+        if rf.result.is_null() && rf.error.is_none() {
+            lock.acceptc += 1;
+            warn!("block {} accepted {:?}", rf.id, costed);
+        } else {
+            lock.rejectc += 1;
+            error!("block {} rejected {:?}, result: {}, error: {:?}", rf.id, costed, rf.result, rf.error_string());
+        }
+    }
+}
+
+impl State<BtcJob> {
+    // the messages of stratum v2, the channel is opened once the connection is set up
+    fn handle_sv2(&self, msg: sv2::Message) -> util::Result<()> {
+        use sv2::Message as M;
+
+        let mut lock = self.value().lock();
+        let lock = &mut *lock;
+        match msg {
+            M::SetupConnectionSuccess { used_version, flags } => {
+                let costed = lock.reqs.remove_until(usize::MAX, METHOD_SETUP_CONNECTION).pop().map(|r| r.time.elapsed());
+                info!("{} ok {:?}, version: {}, flags: {:x}", METHOD_SETUP_CONNECTION, costed, used_version, flags);
+                let req = make_open_channel(self.pool(), self.config().workers);
+                self.sender().clone().try_send(Ok(req)).map_err(|e| error!("try send {} error: {:?}", METHOD_OPEN_CHANNEL, e)).ok();
+            }
+            M::SetupConnectionError { flags, error_code } => {
+                return Err(LoginError::new(METHOD_SETUP_CONNECTION, self.pool(), Some(format!("{}, flags: {:x}", error_code, flags))).into());
+            }
+            M::OpenStandardMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_prefix,
+                ..
+            } => {
+                let costed = lock.reqs.remove(request_id as _).map(|r| r.time.elapsed());
+                let target = target_uint256_from_hashraw(parse_u256(&target)?);
+                info!(
+                    "channel: {}, diff {}: {}, extranonce_prefix: {}, costed: {:?}",
+                    channel_id,
+                    target_to_difficulty(&target),
+                    target,
+                    extranonce_prefix,
+                    costed
+                );
+                lock.job = BtcJob::Channel(Box::new(Channel {
+                    id: channel_id,
+                    target,
+                    ..Default::default()
+                }));
+            }
+            M::OpenMiningChannelError { request_id, error_code } => {
+                lock.reqs.remove(request_id as _);
+                return Err(LoginError::new(METHOD_OPEN_CHANNEL, self.pool(), Some(error_code)).into());
+            }
+            M::NewMiningJob {
+                channel_id,
+                job_id,
+                min_ntime,
+                version,
+                merkle_root,
+            } => {
+                let merkle_root = parse_u256(&merkle_root)?;
+                let channel = match &mut lock.job {
+                    BtcJob::Channel(c) if c.id == channel_id => c,
+                    _ => return Err(format_err!("job {} of unknown channel {}", job_id, channel_id)),
+                };
+                match min_ntime {
+                    Some(ntime) => {
+                        let job = channel.job(job_id, version, merkle_root, ntime).ok_or_else(|| format_err!("job {} without prev_hash", job_id))?;
+                        activate_job(lock, job);
+                    }
+                    None => channel.jobs.push((job_id, version, merkle_root)),
+                }
+            }
+            // the other future jobs are stale
+            M::SetNewPrevHash {
+                channel_id,
+                job_id,
+                prev_hash,
+                min_ntime,
+                nbits,
+            } => {
+                let prev_hash = Hash::from_inner(parse_u256(&prev_hash)?);
+                let channel = match &mut lock.job {
+                    BtcJob::Channel(c) if c.id == channel_id => c,
+                    _ => return Err(format_err!("prev_hash {} of unknown channel {}", prev_hash, channel_id)),
+                };
+                channel.prev_hash = Some((prev_hash, nbits));
+                match mem::take(&mut channel.jobs).into_iter().find(|j| j.0 == job_id) {
+                    Some((_, version, merkle_root)) => {
+                        let job = channel.job(job_id, version, merkle_root, min_ntime).expect("prev_hash set");
+                        activate_job(lock, job);
+                    }
+                    None => {
+                        warn!("prev_hash {} of unknown job {}, wait for the next job", prev_hash, job_id);
+                        channel.job = None;
+                        lock.jobsc.add_slow(1);
+                    }
+                }
+            }
+            M::SetTarget { channel_id, maximum_target } => {
+                let target = target_uint256_from_hashraw(parse_u256(&maximum_target)?);
+                if let BtcJob::Channel(c) = &mut lock.job {
+                    info!("channel: {}, diff {}: {}", channel_id, target_to_difficulty(&target), target);
+                    c.target = target;
+                    // the workers clone the job, they take the new target by the next one
+                    if let Some(j) = c.job.as_mut() {
+                        j.target = target;
+                        lock.jobsc.add_slow(1);
+                    }
+                }
+            }
+            M::SubmitSharesSuccess {
+                last_sequence_number,
+                new_submits_accepted_count,
+                ..
+            } => {
+                let reqs = lock.reqs.remove_until(last_sequence_number as _, METHOD_SUBMIT_SHARES);
+                lock.acceptc += new_submits_accepted_count as usize;
+                info!(
+                    "submit {} accepted {} of {}, costed {:?}",
+                    last_sequence_number,
+                    new_submits_accepted_count,
+                    reqs.len(),
+                    reqs.iter().map(|r| r.time.elapsed()).max()
+                );
+            }
+            M::SubmitSharesError { sequence_number, error_code, .. } => {
+                let costed = lock.reqs.remove(sequence_number as _).map(|r| r.time.elapsed());
+                lock.rejectc += 1;
+                error!("submit {} rejected {:?}, error: {}", sequence_number, costed, error_code);
+            }
+            M::Reconnect { new_host, new_port } => {
+                return Err(ReconnectError {
+                    host: Some(new_host).filter(|h| !h.is_empty()),
+                    port: Some(new_port).filter(|p| *p > 0),
+                    wait: Duration::default(),
+                }
+                .into());
+            }
+            other => warn!("unkown sv2 message: {:?}", other),
+        }
+        Ok(())
+    }
+}
+
+// the header-only job of the channel for the workers
+fn activate_job(lock: &mut Statev<BtcJob>, mut j: Job) {
+    j.id = lock.jobsc.get() + 1;
+    info!(
+        "job: {}, diff {}: {}, prevhash: {}, merkle_root: {}, nbits: {:08x}, ntime: {}, version: {:08x}",
+        j.jobid,
+        target_to_difficulty(&j.target),
+        j.target,
+        j.prev_hash,
+        j.standard.map(|s| Hash::from_inner(s.1)).unwrap_or_default(),
+        j.nbits,
+        j.ntime,
+        j.version,
+    );
+    if let BtcJob::Channel(c) = &mut lock.job {
+        c.job = Some(j);
+        lock.jobsc.add_slow(1);
+    }
+}
+
 impl Handle for State<BtcJob> {
-    fn login_request(&self) -> Req {
-        make_login(&self.config())
+    // the node of http(s):// pool has no login, such as bitcoind
+    fn login_request(&self) -> Vec<Req> {
+        if self.pool().addr.scheme.is_http() {
+            return vec![make_template()];
+        }
+        if self.pool().addr.scheme.is_sv2() {
+            return vec![make_setup_connection(self.pool())];
+        }
+        make_login(self.pool())
     }
     fn hashrate_request(&self, _: u64) -> Option<Req> {
         None
     }
+    fn poll_request(&self) -> Option<Req> {
+        Some(make_template()).filter(|_| self.pool().addr.scheme.is_http())
+    }
     fn handle_request(&self, req: Req) -> util::Result<String> {
         let mut lock = self.value().lock();
-        lock.reqs.add(&req);
-        if req.1 == METHOD_SUBMIT_WORK {
+        if req.1 == METHOD_SUBMIT_SHARES {
+            // the sv2 pools may acknowledge the shares in batch, so they wait longer than the other requests
+            lock.reqs.add_with_timeout(&req, Duration::from_secs(SHARES_TIMEOUT_SECS));
+        } else if req.1 != stratum::METHOD_REPLY {
+            lock.reqs.add(&req);
+        }
+        if req.1 == METHOD_SUBMIT_WORK || req.1 == METHOD_SUBMIT_BLOCK || req.1 == METHOD_SUBMIT_SHARES {
             (*lock).submitc += 1;
         }
         trace!("id: {}, method: {}, req: {}", req.0, req.1, req.2);
@@ -54,20 +298,28 @@ impl Handle for State<BtcJob> {
     }
     fn handle_response(&self, resp: String) -> util::Result<()> {
         trace!("resp: {}", resp);
+        if self.pool().addr.scheme.is_sv2() {
+            return self.handle_sv2(serde_json::from_str(&resp)?);
+        }
 
-        if let Ok(jf) = serde_json::from_str::<MethodForm>(&resp) {
-            match jf.to_params() {
+        let msg = stratum::parse(&resp);
+        if let Ok(Message::Notify(notify)) = msg {
+            let notify = match stratum::handle_notify(self, notify)? {
+                Some(notify) => notify,
+                None => return Ok(()),
+            };
+            match parse_notify(notify) {
                 Ok(jt) => {
                     let mut lock = self.value().lock();
                     let lock = &mut *lock;
 
                     match jt {
-                        Either::Left(mut j) => {
+                        MethodParams::Job(mut j) => {
                             j.id = lock.jobsc.get() + 1;
-                            let (nonce1, nonce2_bytes, nonce2_max, target) = match mem::replace(&mut lock.job, BtcJob::Sleep) {
-                                BtcJob::Compute(oj) => (oj.nonce1, oj.nonce2_bytes, oj.nonce2_max, oj.target),
+                            let (nonce1, nonce2_bytes, nonce2_max, target, version_mask) = match mem::replace(&mut lock.job, BtcJob::Sleep) {
+                                BtcJob::Compute(oj) => (oj.nonce1, oj.nonce2_bytes, oj.nonce2_max, oj.target, oj.version_mask),
                                 BtcJob::Nonce1t(n1t) => n1t,
-                                BtcJob::Sleep => {
+                                BtcJob::Sleep | BtcJob::Channel(_) => {
                                     fatal!("job arrived, but nonce1 info is none");
                                 }
                                 BtcJob::Exit => return Ok(()),
@@ -80,9 +332,10 @@ impl Handle for State<BtcJob> {
                             j.target = target;
                             j.nonce2_max = nonce2_max;
                             j.nonce2_bytes = nonce2_bytes;
+                            j.version_mask = version_mask;
 
                             info!(
-                                "job: {}, diff {}: {}, prevhash: {}, nonce1: {}, nonce2: {:x}, nbits: {}, ntime: {}, version: {}",
+                                "job: {}, diff {}: {}, prevhash: {}, nonce1: {}, nonce2: {:x}, nbits: {}, ntime: {}, version: {}, mask: {:08x}",
                                 j.jobid,
                                 target_to_difficulty(&j.target),
                                 j.target,
@@ -92,16 +345,17 @@ impl Handle for State<BtcJob> {
                                 j.nbits,
                                 j.ntime,
                                 j.version,
+                                j.version_mask,
                             );
                             let js = BtcJob::Compute(j);
                             lock.job = js;
                             lock.jobsc.add_slow(1);
                         }
-                        Either::Right(diff) => {
+                        MethodParams::Target(diff) => {
                             let target = unit_target() / Uint256::from_u64(diff).unwrap();
                             let job = match mem::replace(&mut lock.job, BtcJob::Sleep) {
-                                BtcJob::Sleep => BtcJob::Nonce1t(("".to_owned(), 0, 0, target)),
-                                BtcJob::Nonce1t((n1, n2b, n2m, _)) => BtcJob::Nonce1t((n1, n2b, n2m, target)),
+                                BtcJob::Sleep => BtcJob::Nonce1t(("".to_owned(), 0, 0, target, 0)),
+                                BtcJob::Nonce1t((n1, n2b, n2m, _, vm)) => BtcJob::Nonce1t((n1, n2b, n2m, target, vm)),
                                 BtcJob::Compute(mut job) => {
                                     job.target = target;
                                     BtcJob::Compute(job)
@@ -110,14 +364,23 @@ impl Handle for State<BtcJob> {
                             };
                             lock.job = job;
                         }
+                        MethodParams::Nonce1t((nonce1, nonce2_bytes)) => set_nonce1(lock, nonce1, nonce2_bytes),
+                        MethodParams::VersionMask(mask) => set_version_mask(lock, mask),
                     }
                 }
                 Err(e) => error!("handle job({}) error: {}", resp, e),
             }
-        } else if let Ok(rf) = serde_json::from_str::<ResultForm>(&resp) {
-            // <(id, bool, _), (nonce1, nonce2, _)>
-            match rf.to_result() {
-                Ok(Either::Left((id, b, e))) => {
+        } else if let Ok(Message::Response(rf)) = msg {
+            let method = self.value().lock().reqs.get(rf.id).map(|r| r.method);
+            if method == Some(METHOD_GET_TEMPLATE) {
+                return self.handle_template(&rf);
+            } else if method == Some(METHOD_SUBMIT_BLOCK) {
+                self.handle_submit_block(&rf);
+                return Ok(());
+            }
+
+            match parse_response(&rf) {
+                Ok(ResponseParams::Result((id, b, e))) => {
                     let mut lock = self.value().lock();
                     let lock = &mut *lock;
 
@@ -131,6 +394,8 @@ impl Handle for State<BtcJob> {
                                 lock.rejectc += 1;
                                 error!("submit {} rejected {:?}, error: {:?}", req.id, costed, e);
                             }
+                        } else if req.method == METHOD_AUTHORIZE && !b {
+                            return Err(LoginError::new(req.method, self.pool(), e).into());
                         } else {
                             info!("request {}#{} {:?}, error: {:?}", req.id, req.method, costed, e);
                         }
@@ -138,24 +403,20 @@ impl Handle for State<BtcJob> {
                         warn!("unkown response id: {}, result: {}, error: {:?}", id, b, e);
                     }
                 }
-                Ok(Either::Right((nonce1, nonce2_bytes, e))) => {
-                    let nonce2_max = 2u128.pow(8 * nonce2_bytes as u32);
-                    info!("nonce1: {}, nonce2_bytes: {}, nonce2_max: {:x}, error: {:?}", nonce1, nonce2_bytes, nonce2_max, e);
-
+                Ok(ResponseParams::Nonce1t((nonce1, nonce2_bytes, e))) => {
+                    if let Some(e) = e {
+                        warn!("{} error: {}", METHOD_SUBSCRIBE, e);
+                    }
                     let mut lock = self.value().lock();
                     let lock = &mut *lock;
-                    let job = match mem::replace(&mut lock.job, BtcJob::Sleep) {
-                        BtcJob::Sleep => BtcJob::Nonce1t((nonce1, nonce2_bytes, nonce2_max, Default::default())),
-                        BtcJob::Nonce1t((_, _, _, t)) => BtcJob::Nonce1t((nonce1, nonce2_bytes, nonce2_max, t)),
-                        BtcJob::Compute(mut j) => {
-                            j.nonce1 = nonce1;
-                            j.nonce2_bytes = nonce2_bytes;
-                            j.nonce2_max = nonce2_max;
-                            BtcJob::Compute(j)
-                        }
-                        other => other,
-                    };
-                    lock.job = job;
+                    lock.reqs.remove(rf.id);
+                    set_nonce1(lock, nonce1, nonce2_bytes);
+                }
+                Ok(ResponseParams::VersionMask((id, mask))) => {
+                    let mut lock = self.value().lock();
+                    let lock = &mut *lock;
+                    lock.reqs.remove(id);
+                    set_version_mask(lock, mask);
                 }
                 Err(e) => error!("handle result({}) error:  {}", resp, e),
             }
@@ -167,6 +428,45 @@ impl Handle for State<BtcJob> {
     }
 }
 
+// the nonce1 from the subscribe response or mining.set_extranonce, the workers restart the current job with the new one
+fn set_nonce1(lock: &mut Statev<BtcJob>, nonce1: String, nonce2_bytes: usize) {
+    let nonce2_max = 2u128.pow(8 * nonce2_bytes as u32);
+    info!("nonce1: {}, nonce2_bytes: {}, nonce2_max: {:x}", nonce1, nonce2_bytes, nonce2_max);
+
+    let job = match mem::replace(&mut lock.job, BtcJob::Sleep) {
+        BtcJob::Sleep => BtcJob::Nonce1t((nonce1, nonce2_bytes, nonce2_max, Default::default(), 0)),
+        BtcJob::Nonce1t((_, _, _, t, vm)) => BtcJob::Nonce1t((nonce1, nonce2_bytes, nonce2_max, t, vm)),
+        BtcJob::Compute(mut j) => {
+            j.nonce1 = nonce1;
+            j.nonce2_bytes = nonce2_bytes;
+            j.nonce2_max = nonce2_max;
+            j.nonce2 %= nonce2_max;
+            lock.jobsc.add_slow(1);
+            BtcJob::Compute(j)
+        }
+        other => other,
+    };
+    lock.job = job;
+}
+
+// the version mask from mining.configure or mining.set_version_mask(BIP310), 0 disables the version rolling
+fn set_version_mask(lock: &mut Statev<BtcJob>, mask: u32) {
+    info!("version mask: {:08x}", mask);
+
+    let job = match mem::replace(&mut lock.job, BtcJob::Sleep) {
+        BtcJob::Sleep => BtcJob::Nonce1t(("".to_owned(), 0, 0, Default::default(), mask)),
+        BtcJob::Nonce1t((n1, n2b, n2m, t, _)) => BtcJob::Nonce1t((n1, n2b, n2m, t, mask)),
+        BtcJob::Compute(mut j) => {
+            j.version_mask = mask;
+            j.version_bits = 0;
+            lock.jobsc.add_slow(1);
+            BtcJob::Compute(j)
+        }
+        other => other,
+    };
+    lock.job = job;
+}
+
 impl Run for Worker<BtcJob> {
     fn run(&mut self) {
         let mut job_idx = 0;
@@ -185,21 +485,33 @@ impl Run for Worker<BtcJob> {
                 };
 
                 job_idx = job_idx2;
+                // the active job of the stratum v2 channel
+                let newjob = match newjob {
+                    BtcJob::Channel(c) => c.job.map(|j| BtcJob::Compute(Box::new(j))).unwrap_or_default(),
+                    other => other,
+                };
                 match newjob {
+                    // the header-only jobs have no nonce2, the workers are split by the version bits
+                    BtcJob::Compute(mut j) if j.standard.is_some() => {
+                        let ok = j.roll_version_by(self.idx);
+                        job = Some(*j).filter(|_| ok);
+                        job.iter().for_each(|j| computer.update(j));
+                        nonce = 0;
+                    }
                     BtcJob::Compute(mut j) => {
                         j.nonce2 += self.idx as u128;
                         computer.update(&j);
-                        job = Some(j);
+                        job = Some(*j);
                         nonce = 0;
                     }
-                    BtcJob::Sleep => job = None,
+                    BtcJob::Channel(_) | BtcJob::Sleep => job = None,
                     BtcJob::Nonce1t(..) => job = None,
                     BtcJob::Exit => break,
                 }
             }
 
             if let Some(j) = job.as_mut() {
-                if let Some(solution) = computer.compute(&*j, nonce) {
+                for solution in computer.compute(&*j, nonce) {
                     warn!(
                         "found a solution: id: {}, nonce1&2: {} {:x}, nonce: {:0x}, jobid: {}, diff: {}, target: {}",
                         solution.id,
@@ -213,10 +525,21 @@ impl Run for Worker<BtcJob> {
                     make_submit(&solution, j).map(|req| self.sender.try_send(Ok(req)).map_err(|e| error!("try send solution error: {:?}", e)).ok());
                     util::sleep_secs(self.sleep);
                 }
-                self.hashrate.add(1);
-                nonce += 1;
+                self.hashrate.add(computer.lanes());
+                nonce = nonce.wrapping_add(computer.lanes() as u32);
 
-                if nonce == 0 {
+                // roll the version bits before the nonce2, it only needs to update the header
+                if nonce == 0 && j.standard.is_some() {
+                    if j.roll_version_by(self.step) {
+                        computer.update_version(j);
+                    } else {
+                        warn!("worker-{} exhausted the version bits of job {}, wait for the next job", self.idx, j.jobid);
+                        job = None;
+                    }
+                } else if nonce == 0 && j.roll_version() {
+                    trace!("worker-{} version rolling: {:08x}", self.idx, j.version_bits);
+                    computer.update_version(j);
+                } else if nonce == 0 {
                     let nonce2 = j.nonce2;
                     j.nonce2 += self.step as u128;
                     info!("worker-{} nonce2 update: {} + {} -> {}", self.idx, nonce2, self.step, j.nonce2);
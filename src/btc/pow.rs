@@ -12,6 +12,8 @@ use std::collections::VecDeque;
 use std::convert::TryInto;
 
 use super::proto::{Job, Solution};
+use super::sha256::{self, Midstate};
+use crate::kernel::{self, Algorithm, Kernel};
 use crate::util::atomic_id;
 
 pub type HashRaw = [u8; 32];
@@ -35,6 +37,29 @@ pub fn cal_merkle_root_by_branch(mut tx_hash: VecDeque<HashRaw>) -> Hash {
     Hash::from_inner(root)
 }
 
+// the branch of the first(coinbase) transaction for cal_merkle_root_by_branch, the first of hashes is skipped
+pub fn merkle_branches(hashes: &[HashRaw]) -> VecDeque<HashRaw> {
+    let mut branches = VecDeque::new();
+    let mut level = hashes.to_vec();
+    let mut data = Vec::with_capacity(32 * 2);
+    while level.len() > 1 {
+        branches.push_back(level[1]);
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1]);
+        }
+        // the hashes paired with the coinbase branch are unknown
+        let mut next = vec![[0u8; 32]];
+        for pair in level[2..].chunks(2) {
+            data.clear();
+            data.extend_from_slice(&pair[0]);
+            data.extend_from_slice(&pair[1]);
+            next.push(sha256d(data.as_slice()));
+        }
+        level = next;
+    }
+    branches
+}
+
 fn assemble_coinbase_tx(coinbase1: &[u8], coinbase2: &[u8], extra_nonce1: &[u8], extra_nonce2: &[u8]) -> Vec<u8> {
     let mut c = Vec::with_capacity(coinbase1.len() + coinbase2.len() + extra_nonce1.len() + extra_nonce2.len());
     c.extend_from_slice(coinbase1);
@@ -150,67 +175,94 @@ pub fn target_to_difficulty(target: &Uint256) -> u64 {
     (unit_target() / *target).low_u64()
 }
 
+// the header of the job with the coinbase of its nonce1 and nonce2, or the merkle root of the header-only job
+pub fn block_header(job: &Job, nonce: u32) -> BlockHeader {
+    let merkle_root = match job.standard {
+        Some((_, merkle_root)) => Hash::from_inner(merkle_root),
+        None => {
+            let nonce1 = decode(&job.nonce1).unwrap();
+            let nonce2 = job.nonce2_bytes();
+            let txid = coinbase_for_block(job.coinbase_part1.as_slice(), job.coinbase_part2.as_slice(), nonce1.as_slice(), nonce2.as_slice())
+                .unwrap()
+                .txid();
+            let mut merkle_branchs = job.merkle_branches.clone();
+            merkle_branchs.push_front(txid.as_hash().into_inner());
+            cal_merkle_root_by_branch(merkle_branchs)
+        }
+    };
+    let merkle_root: TxMerkleNode = merkle_root.into();
+
+    BlockHeader {
+        merkle_root,
+        time: job.ntime,
+        bits: job.nbits,
+        version: job.header_version(),
+        prev_blockhash: job.prev_hash.into(),
+        nonce,
+    }
+}
+
 #[derive(Clone)]
 pub struct Computer {
     bytes: [u8; 80],
+    midstate: Midstate,
+    kernel: Kernel,
 }
 
 impl Computer {
     pub fn new() -> Self {
-        Self { bytes: [0; 80] }
+        Self {
+            bytes: [0; 80],
+            midstate: Default::default(),
+            kernel: kernel::get(Algorithm::Sha256d),
+        }
+    }
+    // the nonces checked by a compute
+    pub fn lanes(&self) -> usize {
+        sha256::lanes(self.kernel)
     }
     pub fn update(&mut self, job: &Job) {
-        let nonce1 = decode(&job.nonce1).unwrap();
-        let nonce2 = job.nonce2_bytes();
-        let txid = coinbase_for_block(job.coinbase_part1.as_slice(), job.coinbase_part2.as_slice(), nonce1.as_slice(), nonce2.as_slice())
-            .unwrap()
-            .txid();
-        let mut merkle_branchs = job.merkle_branches.clone();
-        merkle_branchs.push_front(txid.as_hash().into_inner());
-
-        let merkle_root = cal_merkle_root_by_branch(merkle_branchs);
-        let merkle_root: TxMerkleNode = merkle_root.into();
-
-        let header = BlockHeader {
-            merkle_root,
-            time: job.ntime,
-            bits: job.nbits,
-            version: job.version,
-            prev_blockhash: job.prev_hash.into(),
-            nonce: 0,
-        };
+        let header = block_header(job, 0);
 
         let mut encoder = std::io::Cursor::new(vec![]);
         header.consensus_encode(&mut encoder).unwrap();
         let header_bytes = encoder.into_inner();
         self.bytes.copy_from_slice(header_bytes.as_slice());
+        self.midstate = Midstate::new(&self.bytes);
     }
 
-    #[inline]
-    pub fn compute(&mut self, job: &Job, nonce: u32) -> Option<Solution> {
-        let bytes = &mut self.bytes;
-        (&mut bytes[76..]).copy_from_slice(&nonce.to_le_bytes());
-
-        let hashraw = sha256d(bytes);
-        let target = target_uint256_from_hashraw(hashraw);
-        if target <= job.target {
-            return Some(Solution { target, nonce, id: atomic_id() });
-        }
-
-        None
+    // the version is the first 4 bytes of header
+    pub fn update_version(&mut self, job: &Job) {
+        self.bytes[..4].copy_from_slice(&job.header_version().to_le_bytes());
+        self.midstate = Midstate::new(&self.bytes);
     }
-}
 
-#[cfg(any(feature = "btc-openssl"))]
-#[inline]
-fn sha256d(bytes: &[u8]) -> HashRaw {
-    use openssl::sha::sha256;
-    sha256(sha256(bytes).as_ref())
+    // checks the nonces from nonce to nonce + lanes - 1, all the lanes meeting the target are solutions
+    #[inline]
+    pub fn compute(&mut self, job: &Job, nonce: u32) -> Vec<Solution> {
+        // the top 32 bits of target, most of the hashes are greater than it
+        let target_top = (job.target.0[3] >> 32) as u32;
+        let mut solutions = vec![];
+
+        sha256::sha256d(self.kernel, &self.midstate, nonce, |i, digest| {
+            if digest[7].swap_bytes() > target_top {
+                return false;
+            }
+            let target = target_uint256_from_hashraw(sha256::to_hashraw(digest));
+            if target <= job.target {
+                let nonce = nonce.wrapping_add(i as u32);
+                solutions.push(Solution { target, nonce, id: atomic_id() });
+            }
+            false
+        });
+
+        solutions
+    }
 }
 
-#[cfg(any(feature = "btc-ring"))]
+// the nonces are hashed by the kernels of sha256.rs, this one is for the merkle root and the witness commitment
 #[inline]
-fn sha256d(bytes: &[u8]) -> HashRaw {
+pub fn sha256d(bytes: &[u8]) -> HashRaw {
     use ring::digest;
     digest::digest(&digest::SHA256, digest::digest(&digest::SHA256, bytes).as_ref()).as_ref().try_into().unwrap()
 }
@@ -225,3 +277,26 @@ fn btc_sha256d() {
         assert_eq!(h0, h1, "sha256d impl error: {}", s);
     }
 }
+
+#[test]
+fn test_compute_all_lanes() {
+    use super::proto::Channel;
+
+    let channel = Channel {
+        target: Uint256([u64::MAX; 4]),
+        prev_hash: Some((Hash::default(), 0x207fffff)),
+        ..Default::default()
+    };
+    let mut job = channel.job(1, 0x20000000, [0x11; 32], 1_700_000_000).unwrap();
+    let mut computer = Computer::new();
+    computer.update(&job);
+    // every lane of the batch meets the max target
+    let solutions = computer.compute(&job, 0x100);
+    assert_eq!(solutions.iter().map(|s| s.nonce).collect::<Vec<_>>(), (0x100..0x100 + computer.lanes() as u32).collect::<Vec<_>>());
+    for s in &solutions {
+        assert_eq!(s.target, target_uint256_from_hashraw(block_header(&job, s.nonce).block_hash().into_inner()));
+    }
+
+    job.target = solutions.iter().map(|s| s.target).min().unwrap();
+    assert_eq!(computer.compute(&job, 0x100).len(), 1);
+}
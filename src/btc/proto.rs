@@ -1,24 +1,23 @@
+use bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoin::blockdata::script::{Builder, Script};
+use bitcoin::consensus::encode::{Encodable, VarInt};
 use bitcoin::util::uint::Uint256;
+use bitcoin::{OutPoint, TxOut};
 use bitcoin_hashes::sha256d::Hash;
+use bitcoin_hashes::Hash as HashTrait;
 
 use faster_hex::{hex_decode, hex_string};
-use futures::future::Either;
 use serde_json::Value;
 
 use std::collections::VecDeque;
+use std::sync::Arc;
 
-use super::pow::HashRaw;
-use crate::config::Config;
+use super::pow::{block_header, cal_merkle_root_by_branch, coinbase_for_block, merkle_branches, sha256d, target_uint256_from_hashraw, HashRaw};
+use crate::config::{Pool, LOGIN_STRATUM};
 use crate::state::Req;
-
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct MethodForm {
-    pub id: Option<Value>,
-    pub method: String,
-    pub params: Value,
-    pub error: Option<Value>,
-}
+use crate::stratum::{Notify, Request, Response, METHOD_SET_EXTRANONCE};
+use crate::sv2;
+use crate::util::atomic_id;
 
 // {"id":null,"method":"mining.set_difficulty","params":[1]}
 pub type FormSetDifficulty = (u64,);
@@ -73,6 +72,8 @@ pub fn parse_job(form: FormJob) -> Result<Job, crate::util::Error> {
         nonce2: 0,
         nonce2_max: 0,
         nonce2_bytes: 0,
+        version_mask: 0,
+        version_bits: 0,
         target: Default::default(),
         nonce1: Default::default(),
         jobid,
@@ -84,22 +85,46 @@ pub fn parse_job(form: FormJob) -> Result<Job, crate::util::Error> {
         prev_hash,
         coinbase_part1,
         coinbase_part2,
+        transactions: None,
+        standard: None,
     })
 }
 
-impl MethodForm {
-    pub fn to_params(self) -> Result<Either<Job, u64>, crate::util::Error> {
-        let method = self.method.as_str();
-        if method == METHOD_NOTIFY {
-            serde_json::from_value(self.params).map_err(Into::into).and_then(|p: FormJob| parse_job(p).map(Either::Left))
-        } else if method == METHOD_SET_TARGET {
-            serde_json::from_value(self.params).map_err(Into::into).map(|p: FormSetDifficulty| Either::Right(p.0))
-        } else {
-            Err(format_err!("unkown MethodForm"))
-        }
+// {"id":null,"method":"mining.set_extranonce","params":["08000002",4]}
+pub type FormSetExtranonce = (String, usize);
+
+// {"id":null,"method":"mining.set_version_mask","params":["1fffe000"]}
+pub type FormSetVersionMask = (String,);
+
+pub fn parse_version_mask(mask: &str) -> Result<u32, crate::util::Error> {
+    u32::from_str_radix(mask, 16).map_err(|e| format_err!("invalid version mask {}: {}", mask, e))
+}
+
+pub fn parse_notify(notify: Notify) -> Result<MethodParams, crate::util::Error> {
+    let method = notify.method.as_str();
+    if method == METHOD_NOTIFY {
+        notify.params().and_then(|p: FormJob| parse_job(p).map(|j| MethodParams::Job(Box::new(j))))
+    } else if method == METHOD_SET_TARGET {
+        notify.params().map(|p: FormSetDifficulty| MethodParams::Target(p.0))
+    } else if method == METHOD_SET_EXTRANONCE {
+        notify.params().map(MethodParams::Nonce1t)
+    } else if method == METHOD_SET_VERSION_MASK {
+        notify.params().and_then(|p: FormSetVersionMask| parse_version_mask(&p.0).map(MethodParams::VersionMask))
+    } else {
+        Err(format_err!("unkown notify: {}", method))
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum MethodParams {
+    Job(Box<Job>),
+    // difficulty
+    Target(u64),
+    // (nonce1, nonce2_bytes)
+    Nonce1t((String, usize)),
+    VersionMask(u32),
+}
+
 #[derive(Debug, Clone, Hash)]
 pub struct Job {
     pub id: usize,
@@ -112,11 +137,18 @@ pub struct Job {
     pub nbits: u32,
     pub ntime: u32,
     pub version: i32,
+    // the bits of version allowed to roll by the pool(BIP310), 0 if disabled
+    pub version_mask: u32,
+    pub version_bits: u32,
     pub coinbase_part1: Vec<u8>,
     pub coinbase_part2: Vec<u8>,
     pub merkle_branches: VecDeque<HashRaw>,
     pub prev_hash: Hash,
     pub clean: bool,
+    // the transactions(hex) after the coinbase of getblocktemplate, the solutions are submitted as the blocks
+    pub transactions: Option<Arc<Vec<String>>>,
+    // (channel_id, merkle_root) of the header-only job of stratum v2, it has no coinbase to roll
+    pub standard: Option<(u32, HashRaw)>,
 }
 
 impl Job {
@@ -124,6 +156,19 @@ impl Job {
         let nonce2 = self.nonce2.to_le_bytes();
         nonce2[..self.nonce2_bytes].to_vec()
     }
+    // the version of the header with the rolled bits
+    pub fn header_version(&self) -> i32 {
+        ((self.version as u32 & !self.version_mask) | (self.version_bits & self.version_mask)) as i32
+    }
+    // the next combination of the bits in the mask, false if all of them are used and it wraps to 0
+    pub fn roll_version(&mut self) -> bool {
+        self.version_bits = (self.version_bits | !self.version_mask).wrapping_add(1) & self.version_mask;
+        self.version_bits != 0
+    }
+    // the combination after so many ones, the workers of header-only jobs are split by it
+    pub fn roll_version_by(&mut self, n: u64) -> bool {
+        (0..n).all(|_| self.roll_version())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -133,16 +178,26 @@ pub struct Solution {
     pub nonce: u32,
 }
 
+pub const METHOD_CONFIGURE: &str = "mining.configure";
+pub const METHOD_SET_VERSION_MASK: &str = "mining.set_version_mask";
 pub const METHOD_SUBSCRIBE: &str = "mining.subscribe";
 pub const METHOD_AUTHORIZE: &str = "mining.authorize";
 pub const METHOD_SET_TARGET: &str = "mining.set_difficulty";
 pub const METHOD_NOTIFY: &str = "mining.notify";
 pub const METHOD_SUBMIT_WORK: &str = "mining.submit";
 
-// [user, jobid, nonce2, ntime, nonce]
+// [user, jobid, nonce2, ntime, nonce, version_bits(if version rolling)]
 // {"method": "mining.submit", "params": ["sp_yos.cpux", "Jy4KZryMoz0=", "0400000000000000", "5e968131", "24b9d8f9"], "id":8}
+// {"method": "mining.submit", "params": ["sp_yos.cpux", "Jy4KZryMoz0=", "0400000000000000", "5e968131", "24b9d8f9", "00a00000"], "id":8}
 // {"id":8,"result":true,"error":null}
 pub fn make_submit(solution: &Solution, job: &Job) -> Option<Req> {
+    if let Some(transactions) = job.transactions.as_ref() {
+        return Some(make_submit_block(solution, job, transactions));
+    }
+    if let Some((channel_id, _)) = job.standard {
+        return make_submit_shares(solution, job, channel_id);
+    }
+
     let nonce2 = job.nonce2_bytes();
     let nonce2_submit = hex_string(nonce2.as_slice());
 
@@ -152,51 +207,433 @@ pub fn make_submit(solution: &Solution, job: &Job) -> Option<Req> {
     let nonce = solution.nonce.to_be_bytes();
     let nonce_submit = hex_string(nonce.as_ref());
 
-    let req = format!(
-        r#"{{"id":{},"method":"{}","params":["{}", "{}", "{}", "{}", "{}"]}}"#,
-        solution.id, METHOD_SUBMIT_WORK, "", job.jobid, nonce2_submit, ntime_submit, nonce_submit
-    );
-    Some((solution.id, METHOD_SUBMIT_WORK, req).into())
+    let req = if job.version_mask == 0 {
+        let params = ("", &job.jobid, nonce2_submit, ntime_submit, nonce_submit);
+        Request::new(solution.id, METHOD_SUBMIT_WORK, params).into_req()
+    } else {
+        let version_bits = hex_string((job.version_bits & job.version_mask).to_be_bytes().as_ref());
+        let params = ("", &job.jobid, nonce2_submit, ntime_submit, nonce_submit, version_bits);
+        Request::new(solution.id, METHOD_SUBMIT_WORK, params).into_req()
+    };
+    Some(req)
 }
 
+// r: {"id": 1, "method": "mining.configure", "params": [["version-rolling"], {"version-rolling.mask": "1fffe000", "version-rolling.min-bit-count": 2}]}
+// p: {"id":1,"result":{"version-rolling":true,"version-rolling.mask":"1fffe000"},"error":null}
+
 // r: {"id": 1, "method": "mining.subscribe", "params": ["cpuminer/2.5.0"]}
 // p: {"id":1,"result":[[["mining.notify","ca53a260"]],"ca53a260",8],"error":null}
 // [ [["", session id]], "nonce1", nonce2-bytes ]
 
 // r: {"id": 2, "method": "mining.authorize", "params": ["sp_yos.cpux", ""]}
 // p: {"id":2,"result":true,"error":null}
-pub fn make_login(config: &Config) -> Req {
-    let login = format!(
-        r#"{{"id":0,"method":"{}","params":["{}-{}",null]}}
-{{"id":0,"method":"{}","params":["{}.{}","x"]}}"#,
-        METHOD_SUBSCRIBE,
-        env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION"),
-        METHOD_AUTHORIZE,
-        config.user,
-        config.rig
-    );
-    (0, METHOD_SUBSCRIBE, login).into()
+pub fn make_login(pool: &Pool) -> Vec<Req> {
+    let (configure_id, subscribe_id, authorize_id) = (atomic_id(), atomic_id(), atomic_id());
+    let agent = format!("{}-{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let extensions = serde_json::json!({
+        "version-rolling.mask": VERSION_ROLLING_MASK,
+        "version-rolling.min-bit-count": VERSION_ROLLING_MIN_BITS,
+    });
+    vec![
+        Request::new(configure_id, METHOD_CONFIGURE, (["version-rolling"], extensions)).into_req(),
+        Request::new(subscribe_id, METHOD_SUBSCRIBE, (agent, ())).into_req(),
+        Request::new(authorize_id, METHOD_AUTHORIZE, pool.login_params(LOGIN_STRATUM)).into_req(),
+    ]
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ResultForm {
-    pub id: usize,
-    pub result: Value,
-    pub error: Option<Value>,
+// the general purpose bits of BIP320
+pub const VERSION_ROLLING_MASK: &str = "1fffe000";
+pub const VERSION_ROLLING_MIN_BITS: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub enum ResponseParams {
+    // (id, bool, error)
+    Result((usize, bool, Option<String>)),
+    // (nonce1, nonce2_bytes, error)
+    Nonce1t((String, usize, Option<String>)),
+    // (id, version mask), the mask is 0 if the pool doesn't support the version rolling
+    VersionMask((usize, u32)),
 }
 
-impl ResultForm {
-    // <(id, bool, _), (nonce1, nonce2, _)>
-    pub fn to_result(&self) -> Result<Either<(usize, bool, Option<String>), (String, usize, Option<String>)>, &'static str> {
-        if let Ok(b) = serde_json::from_value::<bool>(self.result.clone()) {
-            return Ok(Either::Left((self.id, b, self.error.as_ref().map(|e| format!("{:?}", e)))));
-        }
-        if let Ok((_, nonce1, nonce2)) = serde_json::from_value::<(Value, String, usize)>(self.result.clone()) {
-            Ok(Either::Right((nonce1, nonce2, self.error.as_ref().map(|e| format!("{:?}", e)))))
-        } else {
-            Err("Invalid ResultForm")
-        }
+#[derive(Debug, Deserialize)]
+struct FormConfigure {
+    #[serde(rename = "version-rolling")]
+    version_rolling: bool,
+    #[serde(rename = "version-rolling.mask", default)]
+    version_rolling_mask: Option<String>,
+}
+
+pub fn parse_response(resp: &Response) -> Result<ResponseParams, &'static str> {
+    if let Some(b) = resp.to_bool() {
+        return Ok(ResponseParams::Result((resp.id, b, resp.error_string())));
+    }
+    if let Ok((_, nonce1, nonce2)) = resp.result::<(Value, String, usize)>() {
+        Ok(ResponseParams::Nonce1t((nonce1, nonce2, resp.error_string())))
+    } else if let Ok(form) = resp.result::<FormConfigure>() {
+        let mask = match form.version_rolling_mask {
+            Some(mask) if form.version_rolling => parse_version_mask(&mask).map_err(|_| "invalid version-rolling.mask")?,
+            _ => 0,
+        };
+        Ok(ResponseParams::VersionMask((resp.id, mask)))
+    } else {
+        Err("invalid response")
+    }
+}
+
+pub const METHOD_GET_TEMPLATE: &str = "getblocktemplate";
+pub const METHOD_SUBMIT_BLOCK: &str = "submitblock";
+
+// the nonce2 in the scriptSig of the coinbase built for getblocktemplate, without nonce1
+pub const TEMPLATE_NONCE2_BYTES: usize = 8;
+const COINBASE_TAG: &[u8] = b"/cminer/";
+// the header of the witness commitment output(BIP141)
+const WITNESS_COMMITMENT_HEADER: [u8; 4] = [0xaa, 0x21, 0xa9, 0xed];
+
+// the hashes of the template are hex in the display(reversed) order
+#[derive(Debug, Clone, Deserialize)]
+pub struct FormTemplate {
+    pub version: i32,
+    pub previousblockhash: String,
+    pub transactions: Vec<FormTemplateTx>,
+    pub coinbasevalue: u64,
+    pub target: String,
+    pub curtime: u32,
+    pub bits: String,
+    pub height: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FormTemplateTx {
+    pub data: String,
+    pub txid: String,
+    // the wtxid
+    pub hash: Option<String>,
+}
+
+// the segwit rule is required, and the signet rule by signet
+// {"jsonrpc":"2.0","id":3,"method":"getblocktemplate","params":[{"rules":["segwit","signet"]}]}
+pub fn make_template() -> Req {
+    let rules = serde_json::json!({ "rules": ["segwit", "signet"] });
+    Request::new(atomic_id(), METHOD_GET_TEMPLATE, [rules]).jsonrpc().into_req()
+}
+
+// the coinbase pays all to the payout with the witness commitment, its nonce2 is rolled by the workers as the stratum jobs
+pub fn parse_template(form: &FormTemplate, payout: &Script) -> Result<Job, crate::util::Error> {
+    let mut txids = vec![[0u8; 32]];
+    let mut wtxids = vec![[0u8; 32]];
+    for tx in &form.transactions {
+        txids.push(tx.txid.parse::<Hash>()?.into_inner());
+        wtxids.push(tx.hash.as_ref().unwrap_or(&tx.txid).parse::<Hash>()?.into_inner());
+    }
+
+    // the wtxid of coinbase is 0, and its witness reserved value is 0 too by coinbase_for_block
+    let mut witness_branches = merkle_branches(&wtxids);
+    witness_branches.push_front([0u8; 32]);
+    let mut data = cal_merkle_root_by_branch(witness_branches).into_inner().to_vec();
+    data.extend_from_slice(&[0u8; 32]);
+    let mut commitment = WITNESS_COMMITMENT_HEADER.to_vec();
+    commitment.extend_from_slice(&sha256d(&data));
+
+    // BIP34 height first
+    let height = Builder::new().push_int(form.height as i64).into_script();
+    let mut coinbase_part1 = vec![];
+    2i32.consensus_encode(&mut coinbase_part1)?;
+    VarInt(1).consensus_encode(&mut coinbase_part1)?;
+    OutPoint::null().consensus_encode(&mut coinbase_part1)?;
+    VarInt((height.len() + TEMPLATE_NONCE2_BYTES + COINBASE_TAG.len()) as u64).consensus_encode(&mut coinbase_part1)?;
+    coinbase_part1.extend_from_slice(height.as_bytes());
+
+    let outputs = vec![
+        TxOut {
+            value: form.coinbasevalue,
+            script_pubkey: payout.clone(),
+        },
+        TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).push_slice(&commitment).into_script(),
+        },
+    ];
+    let mut coinbase_part2 = COINBASE_TAG.to_vec();
+    u32::MAX.consensus_encode(&mut coinbase_part2)?;
+    outputs.consensus_encode(&mut coinbase_part2)?;
+    0u32.consensus_encode(&mut coinbase_part2)?;
+
+    let mut target = hex::decode(&form.target)?;
+    if target.len() != 32 {
+        return Err(format_err!("invalid target of template: {}", form.target));
+    }
+    target.reverse();
+
+    Ok(Job {
+        id: 0,
+        jobid: format!("{}:{:x}", form.height, form.curtime),
+        target: target_uint256_from_hashraw(&target),
+        nonce1: Default::default(),
+        nonce2: 0,
+        nonce2_max: 0,
+        nonce2_bytes: 0,
+        nbits: u32::from_str_radix(&form.bits, 16)?,
+        ntime: form.curtime,
+        version: form.version,
+        version_mask: 0,
+        version_bits: 0,
+        coinbase_part1,
+        coinbase_part2,
+        merkle_branches: merkle_branches(&txids),
+        prev_hash: form.previousblockhash.parse()?,
+        clean: true,
+        transactions: Some(Arc::new(form.transactions.iter().map(|tx| tx.data.clone()).collect())),
+        standard: None,
+    })
+}
+
+// the result is null if the block is accepted, or the reason such as "high-hash"
+// {"jsonrpc":"2.0","id":9,"method":"submitblock","params":["00000020..."]}
+fn make_submit_block(solution: &Solution, job: &Job, transactions: &[String]) -> Req {
+    let nonce1 = hex::decode(&job.nonce1).expect("nonce1 hex");
+    let coinbase = coinbase_for_block(&job.coinbase_part1, &job.coinbase_part2, &nonce1, &job.nonce2_bytes()).expect("coinbase of template");
+
+    let mut block = vec![];
+    block_header(job, solution.nonce).consensus_encode(&mut block).expect("encode header");
+    VarInt(1 + transactions.len() as u64).consensus_encode(&mut block).expect("encode tx count");
+    coinbase.consensus_encode(&mut block).expect("encode coinbase");
+    let mut hex = hex_string(&block);
+    transactions.iter().for_each(|tx| hex.push_str(tx));
+
+    Request::new(solution.id, METHOD_SUBMIT_BLOCK, [hex]).jsonrpc().into_req()
+}
+
+pub const METHOD_SETUP_CONNECTION: &str = "SetupConnection";
+pub const METHOD_OPEN_CHANNEL: &str = "OpenStandardMiningChannel";
+pub const METHOD_SUBMIT_SHARES: &str = "SubmitSharesStandard";
+
+// the pools derive the initial target from the nominal hashrate, about so many hashes of a cpu core
+const NOMINAL_HASHRATE_PER_WORKER: f32 = 2_000_000.0;
+
+// the standard channel of stratum v2, the jobs are header-only
+#[derive(Debug, Clone, Default)]
+pub struct Channel {
+    pub id: u32,
+    pub target: Uint256,
+    // (job_id, version, merkle_root) of the future jobs waiting for SetNewPrevHash
+    pub jobs: Vec<(u32, u32, HashRaw)>,
+    // (prev_hash, nbits) of the last SetNewPrevHash
+    pub prev_hash: Option<(Hash, u32)>,
+    pub job: Option<Job>,
+}
+
+impl Channel {
+    // the job on the last prev_hash, the bits of BIP320 are free to roll
+    pub fn job(&self, job_id: u32, version: u32, merkle_root: HashRaw, ntime: u32) -> Option<Job> {
+        let (prev_hash, nbits) = self.prev_hash?;
+        Some(Job {
+            id: 0,
+            jobid: job_id.to_string(),
+            target: self.target,
+            nonce1: Default::default(),
+            nonce2: 0,
+            nonce2_max: 0,
+            nonce2_bytes: 0,
+            nbits,
+            ntime,
+            version: version as i32,
+            version_mask: parse_version_mask(VERSION_ROLLING_MASK).expect("VERSION_ROLLING_MASK"),
+            version_bits: 0,
+            coinbase_part1: vec![],
+            coinbase_part2: vec![],
+            merkle_branches: VecDeque::new(),
+            prev_hash,
+            clean: true,
+            transactions: None,
+            standard: Some((self.id, merkle_root)),
+        })
     }
 }
+
+// the U256 of stratum v2 is in the order of the header
+pub fn parse_u256(hex: &str) -> Result<HashRaw, crate::util::Error> {
+    let mut bytes = [0u8; 32];
+    hex_decode(hex.as_bytes(), &mut bytes).map_err(|e| format_err!("invalid U256 {}: {}", hex, e))?;
+    Ok(bytes)
+}
+
+// only the standard jobs are required, the header-only ones
+pub fn make_setup_connection(pool: &Pool) -> Req {
+    let msg = sv2::Message::SetupConnection {
+        protocol: sv2::PROTOCOL_MINING,
+        min_version: sv2::PROTOCOL_VERSION,
+        max_version: sv2::PROTOCOL_VERSION,
+        flags: sv2::REQUIRES_STANDARD_JOBS,
+        endpoint_host: pool.addr.host.clone(),
+        endpoint_port: pool.addr.port,
+        vendor: env!("CARGO_PKG_NAME").to_owned(),
+        hardware_version: "cpu".to_owned(),
+        firmware: env!("CARGO_PKG_VERSION").to_owned(),
+        device_id: pool.rig.clone(),
+    };
+    Req(atomic_id(), METHOD_SETUP_CONNECTION, serde_json::to_string(&msg).expect("SetupConnection json"))
+}
+
+// the user(.rig) is the user_identity, any target is acceptable
+pub fn make_open_channel(pool: &Pool, workers: usize) -> Req {
+    let id = atomic_id();
+    let msg = sv2::Message::OpenStandardMiningChannel {
+        request_id: id as u32,
+        user_identity: pool.login_params(LOGIN_STRATUM).into_iter().next().unwrap_or_default(),
+        nominal_hash_rate: workers as f32 * NOMINAL_HASHRATE_PER_WORKER,
+        max_target: "ff".repeat(32),
+    };
+    Req(id, METHOD_OPEN_CHANNEL, serde_json::to_string(&msg).expect("OpenStandardMiningChannel json"))
+}
+
+// the sequence_number is the id of solution, the version is the rolled one
+fn make_submit_shares(solution: &Solution, job: &Job, channel_id: u32) -> Option<Req> {
+    let msg = sv2::Message::SubmitSharesStandard {
+        channel_id,
+        sequence_number: solution.id as u32,
+        job_id: job.jobid.parse().ok()?,
+        nonce: solution.nonce,
+        ntime: job.ntime,
+        version: job.header_version() as u32,
+    };
+    Some(Req(solution.id, METHOD_SUBMIT_SHARES, serde_json::to_string(&msg).ok()?))
+}
+
+#[test]
+fn test_version_rolling() {
+    let mut job = parse_job((
+        "Jy4KZryMoz0=".to_owned(),
+        "02391661d953700a84f962ca118eb3226a786a9f2018264d000013cc00000000".to_owned(),
+        "".to_owned(),
+        "".to_owned(),
+        vec![],
+        "20000000".to_owned(),
+        "1a2a7f80".to_owned(),
+        "5e968131".to_owned(),
+        false,
+    ))
+    .unwrap();
+    assert!(!job.roll_version());
+    assert_eq!(job.header_version(), 0x20000000);
+
+    job.version_mask = 0x00a00000;
+    let mut versions = vec![job.header_version()];
+    while job.roll_version() {
+        versions.push(job.header_version());
+    }
+    assert_eq!(versions, vec![0x20000000, 0x20200000, 0x20800000, 0x20a00000]);
+
+    job.version_bits = 0x00a00000;
+    let solution = Solution {
+        id: 8,
+        target: Default::default(),
+        nonce: 0x24b9d8f9,
+    };
+    let req = make_submit(&solution, &job).unwrap();
+    assert!(req.2.ends_with(r#""5e968131","24b9d8f9","00a00000"]}"#), "{}", req.2);
+}
+
+#[test]
+fn test_block_template() {
+    use bitcoin::consensus::encode::{deserialize, serialize};
+    use bitcoin::{Block, Transaction, TxIn};
+
+    let tx = |n: u8| Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Hash::hash(&[n]).into(), 0),
+            script_sig: Script::new(),
+            sequence: u32::MAX,
+            witness: if n.is_multiple_of(2) { vec![vec![n; 72], vec![2; 33]] } else { vec![] },
+        }],
+        output: vec![TxOut {
+            value: 1000 * n as u64,
+            script_pubkey: Script::new(),
+        }],
+    };
+    let payout = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".parse::<bitcoin::Address>().unwrap().script_pubkey();
+
+    for count in 0..6 {
+        let txs = (1..=count).map(tx).collect::<Vec<_>>();
+        let form = FormTemplate {
+            version: 0x20000000,
+            previousblockhash: "0000000000000000000000000000000000000000000000000000000000abcdef".to_owned(),
+            transactions: txs
+                .iter()
+                .map(|t| FormTemplateTx {
+                    data: hex::encode(serialize(t)),
+                    txid: t.txid().to_string(),
+                    hash: Some(t.wtxid().to_string()),
+                })
+                .collect(),
+            coinbasevalue: 625_000_000,
+            target: "7fffff0000000000000000000000000000000000000000000000000000000000".to_owned(),
+            curtime: 1_700_000_000,
+            bits: "207fffff".to_owned(),
+            height: 1000,
+        };
+        let mut job = parse_template(&form, &payout).unwrap();
+        job.nonce2_bytes = TEMPLATE_NONCE2_BYTES;
+        job.nonce2 = 0x1234;
+        assert_eq!(job.target, Uint256::from_u64(0x7fffff).unwrap() << 232);
+
+        let solution = Solution {
+            id: 9,
+            target: Default::default(),
+            nonce: 7,
+        };
+        let req = make_submit(&solution, &job).unwrap();
+        assert_eq!(req.1, METHOD_SUBMIT_BLOCK);
+        let hex = serde_json::from_str::<Value>(&req.2).unwrap()["params"][0].as_str().unwrap().to_owned();
+        let block: Block = deserialize(&hex::decode(hex).unwrap()).unwrap();
+        assert_eq!(block.header, block_header(&job, 7));
+        assert!(block.check_merkle_root() && block.check_witness_commitment(), "{} txs", count);
+        assert_eq!(block.bip34_block_height(), Ok(1000));
+        assert_eq!((block.txdata[0].output[0].value, &block.txdata[0].output[0].script_pubkey), (625_000_000, &payout));
+        assert_eq!(&block.txdata[1..], &txs[..]);
+    }
+}
+
+#[test]
+fn test_standard_job() {
+    let root = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+    let mut channel = Channel {
+        id: 3,
+        target: target_uint256_from_hashraw(parse_u256(&"ff".repeat(32)).unwrap()),
+        ..Default::default()
+    };
+    assert!(channel.job(1, 0x20000000, parse_u256(root).unwrap(), 1_700_000_000).is_none());
+    assert!(parse_u256("4a5e").is_err());
+
+    let prev_hash = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    channel.prev_hash = Some((Hash::from_inner(parse_u256(prev_hash).unwrap()), 0x1d00ffff));
+    let mut job = channel.job(1, 0x20000000, parse_u256(root).unwrap(), 1_700_000_000).unwrap();
+    let header = block_header(&job, 7);
+    assert_eq!(hex_string(&header.merkle_root[..]), root);
+    assert_eq!(hex_string(&header.prev_blockhash[..]), prev_hash);
+    assert_eq!((header.version, header.bits, header.nonce), (0x20000000, 0x1d00ffff, 7));
+
+    // the second worker starts from the second combination of the version bits
+    assert!(job.roll_version_by(1));
+    assert_eq!(job.header_version(), 0x20002000);
+    let solution = Solution {
+        id: 9,
+        target: Default::default(),
+        nonce: 7,
+    };
+    let req = make_submit(&solution, &job).unwrap();
+    assert_eq!((req.0, req.1), (9, METHOD_SUBMIT_SHARES));
+    let msg = serde_json::from_str::<sv2::Message>(&req.2).unwrap();
+    assert_eq!(
+        msg,
+        sv2::Message::SubmitSharesStandard {
+            channel_id: 3,
+            sequence_number: 9,
+            job_id: 1,
+            nonce: 7,
+            ntime: 1_700_000_000,
+            version: 0x20002000,
+        }
+    );
+}
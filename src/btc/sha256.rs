@@ -0,0 +1,420 @@
+// the sha256d of block header, the first 64 bytes don't change with the nonce, so their state(midstate) is cached per job,
+// and the kernels hash several nonces per call on the lanes of simd
+use super::pow::HashRaw;
+use crate::kernel::Kernel;
+
+const H0: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// the nonces hashed by a call of the kernel
+pub fn lanes(kernel: Kernel) -> usize {
+    match kernel {
+        Kernel::Avx512 => 16,
+        Kernel::Avx2 => 8,
+        Kernel::Sse2 => 4,
+        Kernel::ShaNi => 2,
+        _ => 1,
+    }
+}
+
+// the sha256d of the header with the nonces from nonce to nonce + lanes - 1, f(lane, digest) returns true to stop,
+// the kernel must be one of Algorithm::Sha256d.kernels()
+#[inline]
+pub fn sha256d<F: FnMut(usize, &[u32; 8]) -> bool>(kernel: Kernel, midstate: &Midstate, nonce: u32, f: F) -> bool {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        match kernel {
+            Kernel::ShaNi => return shani::sha256d(midstate, nonce).iter().any(by_lane(f)),
+            Kernel::Avx512 => return x16::sha256d(midstate, nonce).iter().any(by_lane(f)),
+            Kernel::Avx2 => return x8::sha256d(midstate, nonce).iter().any(by_lane(f)),
+            Kernel::Sse2 => return x4::sha256d(midstate, nonce).iter().any(by_lane(f)),
+            _ => {}
+        }
+    }
+    x1::sha256d(midstate, nonce).iter().any(by_lane(f))
+}
+
+// the digests of the lanes with their indexes
+fn by_lane<F: FnMut(usize, &[u32; 8]) -> bool>(mut f: F) -> impl FnMut(&[u32; 8]) -> bool {
+    let mut lane = 0;
+    move |digest| {
+        lane += 1;
+        f(lane - 1, digest)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Midstate {
+    state: [u32; 8],
+    // merkle root tail, time and bits, the words of header[64..76]
+    tail: [u32; 3],
+}
+
+impl Midstate {
+    pub fn new(header: &[u8; 80]) -> Self {
+        let mut state = H0;
+        let mut block = [0u32; 16];
+        for (w, b) in block.iter_mut().zip(header[..64].chunks(4)) {
+            *w = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+        }
+        x1::compress(&mut state, &mut block);
+
+        let mut tail = [0u32; 3];
+        for (w, b) in tail.iter_mut().zip(header[64..76].chunks(4)) {
+            *w = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+        }
+        Self { state, tail }
+    }
+}
+
+// the digest as the bytes of sha256d, same to ring
+#[inline]
+pub fn to_hashraw(digest: &[u32; 8]) -> HashRaw {
+    let mut bytes = [0u8; 32];
+    for (b, w) in bytes.chunks_mut(4).zip(digest.iter()) {
+        b.copy_from_slice(&w.to_be_bytes());
+    }
+    bytes
+}
+
+// the compression function of sha256 over the lanes type V, by the ops(add, xor, and, andnot, rotr, shr, splat) in scope
+macro_rules! compress {
+    ($state:expr, $w:expr) => {{
+        let s: &mut [V; 8] = $state;
+        let w: &mut [V; 16] = $w;
+        let (mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h) = (s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7]);
+        for (i, k) in K.iter().enumerate() {
+            let wi = if i < 16 {
+                w[i]
+            } else {
+                let (w15, w2) = (w[(i + 1) & 15], w[(i + 14) & 15]);
+                let s0 = xor(xor(rotr(w15, 7), rotr(w15, 18)), shr(w15, 3));
+                let s1 = xor(xor(rotr(w2, 17), rotr(w2, 19)), shr(w2, 10));
+                let v = add(add(w[i & 15], s0), add(w[(i + 9) & 15], s1));
+                w[i & 15] = v;
+                v
+            };
+            let s1 = xor(xor(rotr(e, 6), rotr(e, 11)), rotr(e, 25));
+            let ch = xor(and(e, f), andnot(e, g));
+            let t1 = add(add(add(h, s1), add(ch, splat(*k))), wi);
+            let s0 = xor(xor(rotr(a, 2), rotr(a, 13)), rotr(a, 22));
+            let maj = xor(xor(and(a, b), and(a, c)), and(b, c));
+            let t2 = add(s0, maj);
+            h = g;
+            g = f;
+            f = e;
+            e = add(d, t1);
+            d = c;
+            c = b;
+            b = a;
+            a = add(t1, t2);
+        }
+        for (s, v) in s.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *s = add(*s, *v);
+        }
+    }};
+}
+
+// the second block of header and the block of the first digest
+macro_rules! sha256d {
+    ($midstate:expr, $nonces:expr) => {{
+        let midstate: &Midstate = $midstate;
+        let mut w = [splat(0); 16];
+        w[0] = splat(midstate.tail[0]);
+        w[1] = splat(midstate.tail[1]);
+        w[2] = splat(midstate.tail[2]);
+        w[3] = $nonces;
+        w[4] = splat(0x80000000);
+        w[15] = splat(80 * 8);
+        let mut state = [splat(0); 8];
+        for (s, m) in state.iter_mut().zip(midstate.state.iter()) {
+            *s = splat(*m);
+        }
+        compress!(&mut state, &mut w);
+
+        let mut w = [splat(0); 16];
+        w[..8].copy_from_slice(&state);
+        w[8] = splat(0x80000000);
+        w[15] = splat(32 * 8);
+        let mut digest = [splat(0); 8];
+        for (d, h) in digest.iter_mut().zip(H0.iter()) {
+            *d = splat(*h);
+        }
+        compress!(&mut digest, &mut w);
+        digest
+    }};
+}
+
+pub mod x1 {
+    use super::{Midstate, H0, K};
+
+    type V = u32;
+
+    #[inline(always)]
+    fn splat(a: u32) -> V {
+        a
+    }
+    #[inline(always)]
+    fn add(a: V, b: V) -> V {
+        a.wrapping_add(b)
+    }
+    #[inline(always)]
+    fn xor(a: V, b: V) -> V {
+        a ^ b
+    }
+    #[inline(always)]
+    fn and(a: V, b: V) -> V {
+        a & b
+    }
+    #[inline(always)]
+    fn andnot(a: V, b: V) -> V {
+        !a & b
+    }
+    #[inline(always)]
+    fn shr(a: V, n: u32) -> V {
+        a >> n
+    }
+    #[inline(always)]
+    fn rotr(a: V, n: u32) -> V {
+        a.rotate_right(n)
+    }
+
+    pub fn compress(state: &mut [u32; 8], w: &mut [u32; 16]) {
+        compress!(state, w)
+    }
+
+    // the nonce is little-endian in header
+    pub fn sha256d(midstate: &Midstate, nonce: u32) -> [[u32; 8]; 1] {
+        [sha256d!(midstate, nonce.swap_bytes())]
+    }
+}
+
+macro_rules! simd_kernel {
+    ($mod: ident, $feature: literal, $lanes: literal, $v: ident, $loadu: ident, $storeu: ident, $set1: ident, $add: ident, $xor: ident, $and: ident, $andnot: ident, $or: ident, $srl: ident, $sll: ident) => {
+        #[cfg(target_arch = "x86_64")]
+        pub mod $mod {
+            use super::{Midstate, H0, K};
+            use std::arch::x86_64::*;
+
+            type V = $v;
+
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn splat(a: u32) -> V {
+                $set1(a as i32)
+            }
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn add(a: V, b: V) -> V {
+                $add(a, b)
+            }
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn xor(a: V, b: V) -> V {
+                $xor(a, b)
+            }
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn and(a: V, b: V) -> V {
+                $and(a, b)
+            }
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn andnot(a: V, b: V) -> V {
+                $andnot(a, b)
+            }
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn shr(a: V, n: u32) -> V {
+                $srl(a, _mm_cvtsi32_si128(n as i32))
+            }
+            #[inline]
+            #[target_feature(enable = $feature)]
+            unsafe fn rotr(a: V, n: u32) -> V {
+                $or(shr(a, n), $sll(a, _mm_cvtsi32_si128(32 - n as i32)))
+            }
+
+            /// # Safety
+            /// the cpu must support the target feature of kernel
+            #[target_feature(enable = $feature)]
+            pub unsafe fn sha256d(midstate: &Midstate, nonce: u32) -> [[u32; 8]; $lanes] {
+                let mut nonces = [0u32; $lanes];
+                for (i, n) in nonces.iter_mut().enumerate() {
+                    *n = nonce.wrapping_add(i as u32).swap_bytes();
+                }
+                let digest: [V; 8] = sha256d!(midstate, $loadu(nonces.as_ptr() as *const _));
+
+                let mut out = [[0u32; 8]; $lanes];
+                let mut words = [0u32; $lanes];
+                for (i, d) in digest.iter().enumerate() {
+                    $storeu(words.as_mut_ptr() as *mut _, *d);
+                    for (o, w) in out.iter_mut().zip(words.iter()) {
+                        o[i] = *w;
+                    }
+                }
+                out
+            }
+        }
+    };
+}
+
+simd_kernel!(
+    x4,
+    "sse2",
+    4,
+    __m128i,
+    _mm_loadu_si128,
+    _mm_storeu_si128,
+    _mm_set1_epi32,
+    _mm_add_epi32,
+    _mm_xor_si128,
+    _mm_and_si128,
+    _mm_andnot_si128,
+    _mm_or_si128,
+    _mm_srl_epi32,
+    _mm_sll_epi32
+);
+simd_kernel!(
+    x8,
+    "avx2",
+    8,
+    __m256i,
+    _mm256_loadu_si256,
+    _mm256_storeu_si256,
+    _mm256_set1_epi32,
+    _mm256_add_epi32,
+    _mm256_xor_si256,
+    _mm256_and_si256,
+    _mm256_andnot_si256,
+    _mm256_or_si256,
+    _mm256_srl_epi32,
+    _mm256_sll_epi32
+);
+
+simd_kernel!(
+    x16,
+    "avx512f",
+    16,
+    __m512i,
+    _mm512_loadu_si512,
+    _mm512_storeu_si512,
+    _mm512_set1_epi32,
+    _mm512_add_epi32,
+    _mm512_xor_si512,
+    _mm512_and_si512,
+    _mm512_andnot_si512,
+    _mm512_or_si512,
+    _mm512_srl_epi32,
+    _mm512_sll_epi32
+);
+
+// the sha extensions compute 2 rounds per instruction on the state of (abef, cdgh),
+// 2 nonces are interleaved to hide the latency of sha256rnds2
+#[cfg(target_arch = "x86_64")]
+pub mod shani {
+    use super::{Midstate, H0, K};
+    use std::arch::x86_64::*;
+
+    const WAYS: usize = 2;
+
+    #[inline]
+    #[target_feature(enable = "sha,sse4.1")]
+    unsafe fn compress(states: &mut [[u32; 8]; WAYS], blocks: &[[u32; 16]; WAYS]) {
+        let mut abef = [_mm_setzero_si128(); WAYS];
+        let mut cdgh = [_mm_setzero_si128(); WAYS];
+        let mut w = [[_mm_setzero_si128(); 4]; WAYS];
+        for j in 0..WAYS {
+            let dcba = _mm_loadu_si128(states[j].as_ptr() as *const _);
+            let hgfe = _mm_loadu_si128(states[j].as_ptr().add(4) as *const _);
+            let cdab = _mm_shuffle_epi32::<0xb1>(dcba);
+            let efgh = _mm_shuffle_epi32::<0x1b>(hgfe);
+            abef[j] = _mm_alignr_epi8::<8>(cdab, efgh);
+            cdgh[j] = _mm_blend_epi16::<0xf0>(efgh, cdab);
+            for (i, w) in w[j].iter_mut().enumerate() {
+                *w = _mm_loadu_si128(blocks[j].as_ptr().add(i * 4) as *const _);
+            }
+        }
+        let (abef0, cdgh0) = (abef, cdgh);
+
+        for g in 0..16 {
+            let k = _mm_loadu_si128(K.as_ptr().add(g * 4) as *const _);
+            for j in 0..WAYS {
+                let w = &mut w[j];
+                // w[t..t+4] = msg2(msg1(w[t-16..], w[t-12..]) + w[t-7..], w[t-4..])
+                if g >= 4 {
+                    let t = _mm_add_epi32(_mm_sha256msg1_epu32(w[g & 3], w[(g + 1) & 3]), _mm_alignr_epi8::<4>(w[(g + 3) & 3], w[(g + 2) & 3]));
+                    w[g & 3] = _mm_sha256msg2_epu32(t, w[(g + 3) & 3]);
+                }
+                let msg = _mm_add_epi32(w[g & 3], k);
+                cdgh[j] = _mm_sha256rnds2_epu32(cdgh[j], abef[j], msg);
+                abef[j] = _mm_sha256rnds2_epu32(abef[j], cdgh[j], _mm_shuffle_epi32::<0x0e>(msg));
+            }
+        }
+
+        for j in 0..WAYS {
+            let feba = _mm_shuffle_epi32::<0x1b>(_mm_add_epi32(abef[j], abef0[j]));
+            let dchg = _mm_shuffle_epi32::<0xb1>(_mm_add_epi32(cdgh[j], cdgh0[j]));
+            _mm_storeu_si128(states[j].as_mut_ptr() as *mut _, _mm_blend_epi16::<0xf0>(feba, dchg));
+            _mm_storeu_si128(states[j].as_mut_ptr().add(4) as *mut _, _mm_alignr_epi8::<8>(dchg, feba));
+        }
+    }
+
+    /// # Safety
+    /// the cpu must support sha and sse4.1
+    #[target_feature(enable = "sha,sse4.1")]
+    pub unsafe fn sha256d(midstate: &Midstate, nonce: u32) -> [[u32; 8]; WAYS] {
+        let mut blocks = [[0u32; 16]; WAYS];
+        for (j, block) in blocks.iter_mut().enumerate() {
+            block[..3].copy_from_slice(&midstate.tail);
+            block[3] = nonce.wrapping_add(j as u32).swap_bytes();
+            block[4] = 0x80000000;
+            block[15] = 80 * 8;
+        }
+        let mut states = [midstate.state; WAYS];
+        compress(&mut states, &blocks);
+
+        for (block, state) in blocks.iter_mut().zip(states.iter()) {
+            *block = [0u32; 16];
+            block[..8].copy_from_slice(state);
+            block[8] = 0x80000000;
+            block[15] = 32 * 8;
+        }
+        let mut digests = [H0; WAYS];
+        compress(&mut digests, &blocks);
+        digests
+    }
+}
+
+#[test]
+fn test_sha256d_kernels() {
+    use crate::kernel::{Algorithm, CpuFeatures};
+    use bitcoin_hashes::{sha256d, Hash};
+
+    for _ in 0..64 {
+        let mut header = [0u8; 80];
+        header.iter_mut().for_each(|b| *b = rand::random());
+        let midstate = Midstate::new(&header);
+        let nonce = rand::random::<u32>() | 0xffff_fff0;
+
+        let mut expect = |i: u32| {
+            header[76..].copy_from_slice(&nonce.wrapping_add(i).to_le_bytes());
+            sha256d::Hash::hash(&header).into_inner()
+        };
+
+        for kernel in Algorithm::Sha256d.kernels(&CpuFeatures::detect()) {
+            let mut lanes = 0;
+            sha256d(kernel, &midstate, nonce, |i, d| {
+                assert_eq!(to_hashraw(d), expect(i as u32), "{} lane {}", kernel, i);
+                lanes += 1;
+                false
+            });
+            assert_eq!(lanes, self::lanes(kernel), "{}", kernel);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+// the network profiles of the currencies selected by --chain, each carries its constants
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::config::Currency;
+
+// the seed epoch of ethash is counted by 30000 blocks
+pub const ETHASH_EPOCH_LENGTH: usize = 30000;
+// ECIP-1099 doubles the epoch length of etc from the block
+pub const ETC_ECIP1099_BLOCK: usize = 11_700_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Chain {
+    Btc,
+    Eth,
+    Etc,
+    Ethw,
+    // ethash with the custom epoch length
+    Ethash(usize),
+    Ckb,
+    CkbTestnet,
+    Kas,
+    KasTestnet,
+}
+
+impl Chain {
+    pub const NAMES: &'static str = "btc, eth, etc, ethw, ethash:<epoch length>, ckb, ckb-testnet, kas, kas-testnet";
+
+    // the mainnet, or the testnet of --testnet, etc for eth as before
+    pub fn of(currency: Currency, testnet: bool) -> Self {
+        match (currency, testnet) {
+            (Currency::Btc, _) => Self::Btc,
+            (Currency::Eth, false) => Self::Eth,
+            (Currency::Eth, true) => Self::Etc,
+            (Currency::Ckb, false) => Self::Ckb,
+            (Currency::Ckb, true) => Self::CkbTestnet,
+            (Currency::Kas, false) => Self::Kas,
+            (Currency::Kas, true) => Self::KasTestnet,
+        }
+    }
+    pub fn currency(&self) -> Currency {
+        match self {
+            Self::Btc => Currency::Btc,
+            Self::Eth | Self::Etc | Self::Ethw | Self::Ethash(_) => Currency::Eth,
+            Self::Ckb | Self::CkbTestnet => Currency::Ckb,
+            Self::Kas | Self::KasTestnet => Currency::Kas,
+        }
+    }
+    pub fn is_testnet(&self) -> bool {
+        matches!(self, Self::CkbTestnet | Self::KasTestnet)
+    }
+    // ckb testnet(aggron) hashes the eaglesong by blake2b again
+    pub fn eaglesong_blake2b(&self) -> bool {
+        *self == Self::CkbTestnet
+    }
+    // the dags of the key are the same
+    pub fn dag_key(&self) -> String {
+        match self {
+            Self::Ethash(length) => format!("ethash{}", length),
+            Self::Etc => "etc".to_owned(),
+            _ => "eth".to_owned(),
+        }
+    }
+    pub fn epoch_length(&self, height: usize) -> usize {
+        match self {
+            Self::Etc if height >= ETC_ECIP1099_BLOCK => ETHASH_EPOCH_LENGTH * 2,
+            Self::Ethash(length) => *length,
+            _ => ETHASH_EPOCH_LENGTH,
+        }
+    }
+    // the seed epoch of ethash for the block, the seedhash is hashed so many times
+    pub fn seed_epoch(&self, height: usize) -> usize {
+        match self {
+            Self::Ethash(length) => height / length,
+            _ => height / self.epoch_length(height) * self.epoch_length(height) / ETHASH_EPOCH_LENGTH,
+        }
+    }
+    // the epoch of the dataset sizes for the seed epoch
+    pub fn dataset_epoch(&self, seed_epoch: usize) -> usize {
+        match self {
+            Self::Etc if seed_epoch >= ETC_ECIP1099_BLOCK / ETHASH_EPOCH_LENGTH => seed_epoch / 2,
+            _ => seed_epoch,
+        }
+    }
+    // the seed epoch of the next boundary, and the blocks left before it
+    pub fn next_epoch(&self, height: usize) -> (usize, usize) {
+        let length = self.epoch_length(height);
+        let next = (height / length + 1) * length;
+        (self.seed_epoch(next), next - height)
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Btc => f.write_str("btc"),
+            Self::Eth => f.write_str("eth"),
+            Self::Etc => f.write_str("etc"),
+            Self::Ethw => f.write_str("ethw"),
+            Self::Ethash(length) => write!(f, "ethash:{}", length),
+            Self::Ckb => f.write_str("ckb"),
+            Self::CkbTestnet => f.write_str("ckb-testnet"),
+            Self::Kas => f.write_str("kas"),
+            Self::KasTestnet => f.write_str("kas-testnet"),
+        }
+    }
+}
+
+impl FromStr for Chain {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chain = match s.to_lowercase().as_str() {
+            "btc" => Self::Btc,
+            "eth" => Self::Eth,
+            "etc" => Self::Etc,
+            "ethw" => Self::Ethw,
+            "ckb" => Self::Ckb,
+            "ckb-testnet" => Self::CkbTestnet,
+            "kas" => Self::Kas,
+            "kas-testnet" => Self::KasTestnet,
+            other => match other.strip_prefix("ethash:").map(|l| l.parse::<usize>()) {
+                Some(Ok(length)) if length > 0 => Self::Ethash(length),
+                _ => return Err(format!("unkown chain {}, the supported: {}", s, Self::NAMES)),
+            },
+        };
+        Ok(chain)
+    }
+}
+
+impl TryFrom<String> for Chain {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Chain> for String {
+    fn from(chain: Chain) -> Self {
+        chain.to_string()
+    }
+}
+
+#[test]
+fn test_chain_epoch() {
+    for c in &["eth", "etc", "ethw", "ethash:8000", "ckb-testnet", "kas-testnet"] {
+        assert_eq!(c.parse::<Chain>().unwrap().to_string(), *c);
+    }
+    assert!("ethash:0".parse::<Chain>().is_err());
+    assert!("bch".parse::<Chain>().is_err());
+
+    assert_eq!(Chain::Eth.next_epoch(0), (1, 30000));
+    assert_eq!(Chain::Eth.next_epoch(29999), (1, 1));
+    assert_eq!(Chain::Eth.next_epoch(30000), (2, 30000));
+    // etc switches at the activation block, the epoch 195 of 60000 is seeded by 390
+    assert_eq!(Chain::Etc.seed_epoch(11_699_999), 389);
+    assert_eq!(Chain::Etc.next_epoch(11_699_000), (390, 1000));
+    assert_eq!(Chain::Etc.seed_epoch(11_759_999), 390);
+    assert_eq!(Chain::Etc.next_epoch(11_700_000), (392, 60000));
+    assert_eq!(Chain::Etc.dataset_epoch(389), 389);
+    assert_eq!(Chain::Etc.dataset_epoch(392), 196);
+    assert_eq!(Chain::Ethash(8000).next_epoch(16001), (3, 7999));
+    assert_eq!(Chain::Ethash(8000).dataset_epoch(3), 3);
+}
@@ -1,17 +1,21 @@
 use bigint::H256;
 use futures::future::Either;
-use serde_json;
 use std::mem;
 
+pub mod molecule;
 pub mod pow;
 pub mod proto;
 
-use crate::config::TIMEOUT_SECS;
-use crate::state::{Handle, Handler, Job as JobID, Req, Run, State, Worker};
-use crate::util::{self, target_to_difficulty};
+use crate::config::{Config, TIMEOUT_SECS};
+use crate::state::{Handle, Handler, Job as JobID, Req, Run, State, Statev, Worker};
+use crate::stratum::{self, Message, Response};
+use crate::util::{self, target_to_difficulty, LoginError};
 
 use pow::{parse_nonce, Computer};
-use proto::{make_login, make_submit, Job, MethodForm, ResultForm, METHOD_SUBMIT_WORK};
+use proto::{
+    make_login, make_submit, make_template, parse_notify, parse_response, parse_template, FormTemplate, Job, MethodParams, METHOD_AUTHORIZE, METHOD_GET_TEMPLATE, METHOD_SUBMIT_BLOCK,
+    METHOD_SUBMIT_WORK, METHOD_SUBSCRIBE,
+};
 
 #[derive(Debug, Clone)]
 pub enum CkbJob {
@@ -22,6 +26,8 @@ pub enum CkbJob {
 }
 
 impl JobID for CkbJob {
+    type Shared = ();
+    fn shared(_: &Config) -> Self::Shared {}
     fn jobid(&self) -> String {
         match &self {
             Self::Compute(job) => job.jobid.clone(),
@@ -36,17 +42,86 @@ impl Default for CkbJob {
     }
 }
 
+impl State<CkbJob> {
+    // the job of get_block_template, the same block with only a newer timestamp is skipped
+    fn handle_template(&self, rf: &Response) -> util::Result<()> {
+        let form = rf.result::<FormTemplate>();
+        let mut lock = self.value().lock();
+        let lock = &mut *lock;
+        let req = lock.reqs.remove(rf.id);
+        let form = match form {
+            Ok(form) => form,
+            Err(e) => {
+                error!("{} failed: {}, error: {:?}", METHOD_GET_TEMPLATE, e, rf.error_string());
+                return Ok(());
+            }
+        };
+
+        let mut j = parse_template(&form)?;
+        let renewed = match (&lock.job, j.block.as_ref()) {
+            (CkbJob::Compute(Job { block: Some(ob), .. }), Some(block)) => {
+                let mut header = block.header.clone();
+                header.timestamp = ob.header.timestamp.clone();
+                header == ob.header
+            }
+            (CkbJob::Exit, _) => return Ok(()),
+            _ => false,
+        };
+        if renewed {
+            return Ok(());
+        }
+
+        j.id = lock.jobsc.get() + 1;
+        j.nonce = rand::random::<u64>() as u128;
+        info!(
+            "template: {}, height: {}, powhash: {}, diff: {}, txs: {}, nonce: {:0x}, costed: {:?}",
+            j.jobid,
+            j.height,
+            j.powhash,
+            target_to_difficulty(&j.target),
+            form.transactions.len(),
+            j.nonce,
+            req.map(|r| r.time.elapsed())
+        );
+        lock.job = CkbJob::Compute(j);
+        lock.jobsc.add_slow(1);
+        Ok(())
+    }
+    // the block hash is accepted, or the error of rejected
+    fn handle_submit_block(&self, rf: &Response) {
+        let mut lock = self.value().lock();
+        let lock = &mut *lock;
+        let costed = lock.reqs.remove(rf.id).map(|r| r.time.elapsed());
+        if rf.result.is_string() && rf.error.is_none() {
+            lock.acceptc += 1;
+            warn!("block {} accepted {:?}: {}", rf.id, costed, rf.result);
+        } else {
+            lock.rejectc += 1;
+            error!("block {} rejected {:?}, result: {}, error: {:?}", rf.id, costed, rf.result, rf.error_string());
+        }
+    }
+}
+
 impl Handle for State<CkbJob> {
-    fn login_request(&self) -> Req {
-        make_login(&self.config())
+    // the node of http(s):// pool has no login
+    fn login_request(&self) -> Vec<Req> {
+        if self.pool().addr.scheme.is_http() {
+            return vec![make_template()];
+        }
+        make_login(self.pool())
     }
     fn hashrate_request(&self, _: u64) -> Option<Req> {
         None
     }
+    fn poll_request(&self) -> Option<Req> {
+        Some(make_template()).filter(|_| self.pool().addr.scheme.is_http())
+    }
     fn handle_request(&self, req: Req) -> util::Result<String> {
         let mut lock = self.value().lock();
-        lock.reqs.add(&req);
-        if req.1 == METHOD_SUBMIT_WORK {
+        if req.1 != stratum::METHOD_REPLY {
+            lock.reqs.add(&req);
+        }
+        if req.1 == METHOD_SUBMIT_WORK || req.1 == METHOD_SUBMIT_BLOCK {
             (*lock).submitc += 1;
         }
         trace!("id: {}, method: {}, req: {}", req.0, req.1, req.2);
@@ -55,14 +130,19 @@ impl Handle for State<CkbJob> {
     fn handle_response(&self, resp: String) -> util::Result<()> {
         trace!("resp: {}", resp);
 
-        if let Ok(jf) = serde_json::from_str::<MethodForm>(&resp) {
-            match jf.to_params() {
+        let msg = stratum::parse(&resp);
+        if let Ok(Message::Notify(notify)) = msg {
+            let notify = match stratum::handle_notify(self, notify)? {
+                Some(notify) => notify,
+                None => return Ok(()),
+            };
+            match parse_notify(notify) {
                 Ok(jt) => {
                     let mut lock = self.value().lock();
                     let lock = &mut *lock;
 
                     match jt {
-                        Either::Left(mut j) => {
+                        MethodParams::Job(mut j) => {
                             j.id = lock.jobsc.get() + 1;
                             let (nonce, nonce1_bytes, target) = match mem::replace(&mut lock.job, CkbJob::Sleep) {
                                 CkbJob::Compute(oj) => (oj.nonce, oj.nonce1_bytes, oj.target),
@@ -89,7 +169,7 @@ impl Handle for State<CkbJob> {
                             lock.job = js;
                             lock.jobsc.add_slow(1);
                         }
-                        Either::Right(target) => {
+                        MethodParams::Target(target) => {
                             let job = match mem::replace(&mut lock.job, CkbJob::Sleep) {
                                 CkbJob::Sleep => CkbJob::Nonce1t((0, 0, target)),
                                 CkbJob::Nonce1t((n1, n1b, _)) => CkbJob::Nonce1t((n1, n1b, target)),
@@ -101,13 +181,22 @@ impl Handle for State<CkbJob> {
                             };
                             lock.job = job;
                         }
+                        MethodParams::Nonce1t((nonce1, nonce2_bytes)) => set_nonce1(lock, &nonce1, nonce2_bytes),
                     }
                 }
                 Err(e) => error!("handle job({}) error: {}", resp, e),
             }
-        } else if let Ok(rf) = serde_json::from_str::<ResultForm>(&resp) {
+        } else if let Ok(Message::Response(rf)) = msg {
+            let method = self.value().lock().reqs.get(rf.id).map(|r| r.method);
+            if method == Some(METHOD_GET_TEMPLATE) {
+                return self.handle_template(&rf);
+            } else if method == Some(METHOD_SUBMIT_BLOCK) {
+                self.handle_submit_block(&rf);
+                return Ok(());
+            }
+
             // <(id, bool, _), (nonce1, nonce2, _)>
-            match rf.to_result() {
+            match parse_response(&rf) {
                 Ok(Either::Left((id, b, e))) => {
                     let mut lock = self.value().lock();
                     let lock = &mut *lock;
@@ -122,6 +211,8 @@ impl Handle for State<CkbJob> {
                                 lock.rejectc += 1;
                                 error!("submit {} rejected {:?}, error: {:?}", req.id, costed, e);
                             }
+                        } else if req.method == METHOD_AUTHORIZE && !b {
+                            return Err(LoginError::new(req.method, self.pool(), e).into());
                         } else {
                             info!("request {}#{} {:?}, error: {:?}", req.id, req.method, costed, e);
                         }
@@ -130,22 +221,13 @@ impl Handle for State<CkbJob> {
                     }
                 }
                 Ok(Either::Right((nonce1, nonce2, e))) => {
-                    info!("nonce1: {}, nonce2_bytes: {}, error: {:?}", nonce1, nonce2, e);
-                    let (n1, n1b) = parse_nonce(&nonce1);
-
+                    if let Some(e) = e {
+                        warn!("{} error: {}", METHOD_SUBSCRIBE, e);
+                    }
                     let mut lock = self.value().lock();
                     let lock = &mut *lock;
-                    let job = match mem::replace(&mut lock.job, CkbJob::Sleep) {
-                        CkbJob::Sleep => CkbJob::Nonce1t((n1, n1b, Default::default())),
-                        CkbJob::Nonce1t((_n1, _n1b, t)) => CkbJob::Nonce1t((n1, n1b, t)),
-                        CkbJob::Compute(mut j) => {
-                            j.nonce = n1;
-                            j.nonce1_bytes = n1b;
-                            CkbJob::Compute(j)
-                        }
-                        other => other,
-                    };
-                    lock.job = job;
+                    lock.reqs.remove(rf.id);
+                    set_nonce1(lock, &nonce1, nonce2);
                 }
                 Err(e) => error!("handle result({}) error:  {}", resp, e),
             }
@@ -157,12 +239,31 @@ impl Handle for State<CkbJob> {
     }
 }
 
+// the nonce1 from the subscribe response or mining.set_extranonce, the workers restart the current job with the new one
+fn set_nonce1(lock: &mut Statev<CkbJob>, nonce1: &str, nonce2_bytes: usize) {
+    info!("nonce1: {}, nonce2_bytes: {}", nonce1, nonce2_bytes);
+    let (n1, n1b) = parse_nonce(nonce1);
+
+    let job = match mem::replace(&mut lock.job, CkbJob::Sleep) {
+        CkbJob::Sleep => CkbJob::Nonce1t((n1, n1b, Default::default())),
+        CkbJob::Nonce1t((_n1, _n1b, t)) => CkbJob::Nonce1t((n1, n1b, t)),
+        CkbJob::Compute(mut j) => {
+            j.nonce = n1;
+            j.nonce1_bytes = n1b;
+            lock.jobsc.add_slow(1);
+            CkbJob::Compute(j)
+        }
+        other => other,
+    };
+    lock.job = job;
+}
+
 impl Run for Worker<CkbJob> {
     fn run(&mut self) {
         let mut job_idx = 0;
         let mut job = None;
         let mut nonce = 0u128;
-        let mut computer = Computer::new(self.testnet);
+        let mut computer = Computer::new(self.job.config().chain().eaglesong_blake2b());
 
         loop {
             let job_idx2 = self.jobsc.get();
@@ -0,0 +1,136 @@
+// the molecule serialization of ckb for the hashes of the block built from get_block_template
+// https://github.com/nervosnetwork/ckb/blob/develop/util/gen-types/schemas/blockchain.mol
+use ckb_hash::{blake2b_256, new_blake2b};
+
+pub type Byte32 = [u8; 32];
+
+// the struct is the fields concatenated, and the fixvec is the count(u32 le) then the items
+pub fn fixvec(count: usize, items: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + items.len());
+    out.extend_from_slice(&(count as u32).to_le_bytes());
+    out.extend_from_slice(items);
+    out
+}
+
+pub fn bytes(data: &[u8]) -> Vec<u8> {
+    fixvec(data.len(), data)
+}
+
+// the table and dynvec are the total size and the offsets(u32 le) then the items
+pub fn dynvec(items: &[Vec<u8>]) -> Vec<u8> {
+    let head = 4 * (1 + items.len());
+    let total = head + items.iter().map(|i| i.len()).sum::<usize>();
+    let mut out = Vec::with_capacity(total);
+    out.extend_from_slice(&(total as u32).to_le_bytes());
+    let mut offset = head;
+    for item in items {
+        out.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += item.len();
+    }
+    items.iter().for_each(|i| out.extend_from_slice(i));
+    out
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub compact_target: u32,
+    pub timestamp: u64,
+    pub number: u64,
+    pub epoch: u64,
+    pub parent_hash: Byte32,
+    pub transactions_root: Byte32,
+    pub proposals_hash: Byte32,
+    pub extra_hash: Byte32,
+    pub dao: Byte32,
+    pub nonce: u128,
+}
+
+impl Header {
+    // the RawHeader, the header without nonce
+    pub fn raw(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(192);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&self.compact_target.to_le_bytes());
+        out.extend_from_slice(&self.timestamp.to_le_bytes());
+        out.extend_from_slice(&self.number.to_le_bytes());
+        out.extend_from_slice(&self.epoch.to_le_bytes());
+        for hash in &[self.parent_hash, self.transactions_root, self.proposals_hash, self.extra_hash, self.dao] {
+            out.extend_from_slice(hash);
+        }
+        out
+    }
+    // the eaglesong input is the pow hash and the nonce(u128 le)
+    pub fn pow_hash(&self) -> Byte32 {
+        blake2b_256(self.raw())
+    }
+    pub fn hash(&self) -> Byte32 {
+        let mut data = self.raw();
+        data.extend_from_slice(&self.nonce.to_le_bytes());
+        blake2b_256(data)
+    }
+}
+
+fn merge(left: &Byte32, right: &Byte32) -> Byte32 {
+    let mut hasher = new_blake2b();
+    hasher.update(left);
+    hasher.update(right);
+    let mut out = [0u8; 32];
+    hasher.finalize(&mut out);
+    out
+}
+
+// the root of complete binary merkle tree, the leaves are the last nodes in order
+pub fn merkle_root(leaves: &[Byte32]) -> Byte32 {
+    if leaves.is_empty() {
+        return Default::default();
+    }
+    let mut nodes = vec![[0u8; 32]; leaves.len() - 1];
+    nodes.extend_from_slice(leaves);
+    for i in (0..leaves.len() - 1).rev() {
+        nodes[i] = merge(&nodes[2 * i + 1], &nodes[2 * i + 2]);
+    }
+    nodes[0]
+}
+
+// the transactions root commits both of the hashes and the witness hashes
+pub fn transactions_root(hashes: &[Byte32], witness_hashes: &[Byte32]) -> Byte32 {
+    merkle_root(&[merkle_root(hashes), merkle_root(witness_hashes)])
+}
+
+// the blake2b of the concatenated items, zero for none
+pub fn hash_concat<T: AsRef<[u8]>>(items: &[T]) -> Byte32 {
+    if items.is_empty() {
+        return Default::default();
+    }
+    let mut hasher = new_blake2b();
+    items.iter().for_each(|i| hasher.update(i.as_ref()));
+    let mut out = [0u8; 32];
+    hasher.finalize(&mut out);
+    out
+}
+
+// the uncles hash, or committed with the hash of the extension since ckb2021
+pub fn extra_hash(uncle_hashes: &[Byte32], extension: Option<&[u8]>) -> Byte32 {
+    let uncles_hash = hash_concat(uncle_hashes);
+    match extension {
+        Some(extension) => merge(&uncles_hash, &blake2b_256(extension)),
+        None => uncles_hash,
+    }
+}
+
+#[test]
+fn test_merkle_root() {
+    let leaves = (0..5u8).map(|i| [i; 32]).collect::<Vec<_>>();
+    assert_eq!(merkle_root(&[]), [0u8; 32]);
+    assert_eq!(merkle_root(&leaves[..1]), leaves[0]);
+    assert_eq!(merkle_root(&leaves[..2]), merge(&leaves[0], &leaves[1]));
+    // the leaves of 3 are [_, _, 0, 1, 2], the 0 is merged with the node of 1 and 2
+    assert_eq!(merkle_root(&leaves[..3]), merge(&merge(&leaves[1], &leaves[2]), &leaves[0]));
+    let (n3, n4) = (merge(&leaves[3], &leaves[4]), merge(&leaves[1], &leaves[2]));
+    assert_eq!(merkle_root(&leaves), merge(&merge(&n3, &leaves[0]), &n4));
+
+    assert_eq!(dynvec(&[]), 4u32.to_le_bytes());
+    assert_eq!(dynvec(&[vec![1], vec![2, 3]]), [15, 0, 0, 0, 12, 0, 0, 0, 13, 0, 0, 0, 1, 2, 3]);
+    assert_eq!(Header::default().raw().len(), 192);
+}
@@ -15,7 +15,7 @@ pub struct Computer {
     testnet: bool,
 }
 
-// ckb testnet use eaglesong_blake2b
+// ckb testnet use eaglesong_blake2b, by the chain profile
 // https://github.com/nervosnetwork/ckb/blob/v0.37.0/pow/src/lib.rs#L21
 impl Computer {
     pub fn new(testnet: bool) -> Self {
@@ -1,20 +1,16 @@
-use bigint::H256;
+use bigint::{BigEndianHash, H256, U256};
 use faster_hex::hex_string;
 use futures::future::Either;
 use serde_json::Value;
+use std::convert::TryInto;
+use std::sync::Arc;
 
-use crate::config::Config;
+use crate::config::{Pool, LOGIN_STRATUM};
 use crate::state::Req;
-use crate::util::clean_0x;
+use crate::stratum::{Notify, Request, Response, METHOD_SET_EXTRANONCE};
+use crate::util::{atomic_id, clean_0x, Result as UResult};
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct MethodForm {
-    pub id: Option<Value>,
-    pub method: String,
-    pub params: Value,
-    pub error: Option<Value>,
-}
+use super::molecule::{bytes, dynvec, extra_hash, fixvec, hash_concat, transactions_root, Byte32, Header};
 
 // p: {"id":null,"method":"mining.set_target","params":["000010c6f7000000000000000000000000000000000000000000000000000000"],"error":null}
 pub type FormSetTarget = (String,);
@@ -35,27 +31,35 @@ pub fn parse_job(form: FormJob) -> Result<Job, &'static str> {
         target: Default::default(),
         nonce: 0,
         id: 0,
+        block: None,
     })
 }
 
-impl MethodForm {
-    pub fn to_params(self) -> Result<Either<Job, H256>, &'static str> {
-        let method = self.method.as_str();
-        if method == METHOD_NOTIFY {
-            serde_json::from_value(self.params)
-                .map_err(|_| "deser_notify error")
-                .and_then(|p: FormJob| parse_job(p).map(Either::Left))
-        } else if method == METHOD_SET_TARGET {
-            serde_json::from_value(self.params)
-                .map_err(|_| "deser_target error")
-                .and_then(|p: FormSetTarget| parse_target(p).map(Either::Right))
-        } else {
-            Err("unkown MethodForm")
-        }
+// {"id":null,"method":"mining.set_extranonce","params":["8555fd37",12]}
+pub type FormSetExtranonce = (String, usize);
+
+pub fn parse_notify(notify: Notify) -> Result<MethodParams, &'static str> {
+    let method = notify.method.as_str();
+    if method == METHOD_NOTIFY {
+        notify.params().map_err(|_| "deser_notify error").and_then(|p: FormJob| parse_job(p).map(MethodParams::Job))
+    } else if method == METHOD_SET_TARGET {
+        notify.params().map_err(|_| "deser_target error").and_then(|p: FormSetTarget| parse_target(p).map(MethodParams::Target))
+    } else if method == METHOD_SET_EXTRANONCE {
+        notify.params().map_err(|_| "deser_extranonce error").map(MethodParams::Nonce1t)
+    } else {
+        Err("unkown notify")
     }
 }
 
-#[derive(Debug, Clone, Hash)]
+#[derive(Debug, Clone)]
+pub enum MethodParams {
+    Job(Job),
+    Target(H256),
+    // (nonce1, nonce2_bytes)
+    Nonce1t((String, usize)),
+}
+
+#[derive(Debug, Clone)]
 pub struct Job {
     pub id: usize,
     pub jobid: String,
@@ -64,6 +68,8 @@ pub struct Job {
     pub nonce: u128,
     pub height: u64,
     pub nonce1_bytes: usize,
+    // the block built from the template of node, submitted with the nonce
+    pub block: Option<Arc<FormBlock>>,
 }
 
 #[derive(Debug, Clone)]
@@ -82,12 +88,15 @@ pub const METHOD_SUBMIT_WORK: &str = "mining.submit";
 // {"id":8,"method":"mining.submit","params":["sp_test.worker1","b1404ef2","000000000000000000084148"]}
 // {"id":8,"result":true,"error":null}
 pub fn make_submit(solution: &Solution, job: &Job) -> Option<Req> {
+    if let Some(block) = job.block.as_ref() {
+        return Some(make_submit_block(solution, block));
+    }
+
     let nonce_bytes = solution.nonce.to_be_bytes();
     let nonce_bytes_submit = &nonce_bytes[job.nonce1_bytes..];
     let nonce_submit = hex_string(nonce_bytes_submit);
 
-    let req = format!(r#"{{"id":{},"method":"{}","params":["{}","{}","{}"]}}"#, solution.id, METHOD_SUBMIT_WORK, "", job.jobid, nonce_submit);
-    Some((solution.id, METHOD_SUBMIT_WORK, req).into())
+    Some(Request::new(solution.id, METHOD_SUBMIT_WORK, ("", &job.jobid, nonce_submit)).into_req())
 }
 
 // r: {"id":0,"method":"mining.subscribe","params":["ckbminer-v1.0.0",null]}
@@ -95,38 +104,400 @@ pub fn make_submit(solution: &Solution, job: &Job) -> Option<Req> {
 
 // r: {"id":0,"method":"mining.authorize","params":["sp_test.worker1","x"]}
 // p: {"id":0,"result":true,"error":null}
-pub fn make_login(config: &Config) -> Req {
-    let login = format!(
-        r#"{{"id":0,"method":"{}","params":["{}-{}",null]}}
-{{"id":0,"method":"{}","params":["{}.{}","x"]}}"#,
-        METHOD_SUBSCRIBE,
-        env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION"),
-        METHOD_AUTHORIZE,
-        config.user,
-        config.rig
-    );
-    (0, METHOD_SUBSCRIBE, login).into()
+pub fn make_login(pool: &Pool) -> Vec<Req> {
+    let (subscribe_id, authorize_id) = (atomic_id(), atomic_id());
+    let agent = format!("{}-{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    vec![
+        Request::new(subscribe_id, METHOD_SUBSCRIBE, (agent, ())).into_req(),
+        Request::new(authorize_id, METHOD_AUTHORIZE, pool.login_params(LOGIN_STRATUM)).into_req(),
+    ]
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ResultForm {
-    pub id: usize,
-    pub result: Value,
-    pub error: Option<Value>,
+// <(id, bool, error), (nonce1, nonce2_bytes, error)>
+pub type ResponseParams = Either<(usize, bool, Option<String>), (String, usize, Option<String>)>;
+
+pub fn parse_response(resp: &Response) -> Result<ResponseParams, &'static str> {
+    if let Some(b) = resp.to_bool() {
+        return Ok(Either::Left((resp.id, b, resp.error_string())));
+    }
+    if let Ok((_, nonce1, nonce2)) = resp.result::<(Option<Value>, String, usize)>() {
+        Ok(Either::Right((nonce1, nonce2, resp.error_string())))
+    } else {
+        Err("invalid response")
+    }
+}
+
+pub const METHOD_GET_TEMPLATE: &str = "get_block_template";
+pub const METHOD_SUBMIT_BLOCK: &str = "submit_block";
+
+// the numbers are 0x hex, and the bytes too
+fn parse_uint(s: &str) -> UResult<u128> {
+    u128::from_str_radix(clean_0x(s), 16).map_err(|e| format_err!("invalid number {}: {}", s, e))
 }
 
-impl ResultForm {
-    // <(id, bool, _), (nonce1, nonce2, _)>
-    pub fn to_result(&self) -> Result<Either<(usize, bool, Option<String>), (String, usize, Option<String>)>, &'static str> {
-        if let Ok(b) = serde_json::from_value::<bool>(self.result.clone()) {
-            return Ok(Either::Left((self.id, b, self.error.as_ref().map(|e| format!("{:?}", e)))));
+fn parse_bytes(s: &str) -> UResult<Vec<u8>> {
+    hex::decode(clean_0x(s)).map_err(|e| format_err!("invalid bytes {}: {}", s, e))
+}
+
+fn parse_byte32(s: &str) -> UResult<Byte32> {
+    parse_bytes(s)?.as_slice().try_into().map_err(|_| format_err!("invalid hash: {}", s))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormHeader {
+    pub version: String,
+    pub compact_target: String,
+    pub timestamp: String,
+    pub number: String,
+    pub epoch: String,
+    pub parent_hash: String,
+    pub transactions_root: String,
+    pub proposals_hash: String,
+    // the uncles_hash before ckb2021
+    #[serde(alias = "uncles_hash")]
+    pub extra_hash: String,
+    pub dao: String,
+    pub nonce: String,
+}
+
+impl FormHeader {
+    pub fn to_header(&self) -> UResult<Header> {
+        Ok(Header {
+            version: parse_uint(&self.version)? as _,
+            compact_target: parse_uint(&self.compact_target)? as _,
+            timestamp: parse_uint(&self.timestamp)? as _,
+            number: parse_uint(&self.number)? as _,
+            epoch: parse_uint(&self.epoch)? as _,
+            parent_hash: parse_byte32(&self.parent_hash)?,
+            transactions_root: parse_byte32(&self.transactions_root)?,
+            proposals_hash: parse_byte32(&self.proposals_hash)?,
+            extra_hash: parse_byte32(&self.extra_hash)?,
+            dao: parse_byte32(&self.dao)?,
+            nonce: parse_uint(&self.nonce)?,
+        })
+    }
+    pub fn from_header(header: &Header) -> Self {
+        let hash = |h: &Byte32| format!("0x{}", hex_string(h));
+        Self {
+            version: format!("{:#x}", header.version),
+            compact_target: format!("{:#x}", header.compact_target),
+            timestamp: format!("{:#x}", header.timestamp),
+            number: format!("{:#x}", header.number),
+            epoch: format!("{:#x}", header.epoch),
+            parent_hash: hash(&header.parent_hash),
+            transactions_root: hash(&header.transactions_root),
+            proposals_hash: hash(&header.proposals_hash),
+            extra_hash: hash(&header.extra_hash),
+            dao: hash(&header.dao),
+            nonce: format!("{:#x}", header.nonce),
         }
-        if let Ok((_, nonce1, nonce2)) = serde_json::from_value::<(Option<Value>, String, usize)>(self.result.clone()) {
-            Ok(Either::Right((nonce1, nonce2, self.error.as_ref().map(|e| format!("{:?}", e)))))
-        } else {
-            Err("Invalid ResultForm")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormOutPoint {
+    pub tx_hash: String,
+    pub index: String,
+}
+
+impl FormOutPoint {
+    fn to_molecule(&self) -> UResult<Vec<u8>> {
+        let mut out = parse_byte32(&self.tx_hash)?.to_vec();
+        out.extend_from_slice(&(parse_uint(&self.index)? as u32).to_le_bytes());
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormCellDep {
+    pub out_point: FormOutPoint,
+    pub dep_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormCellInput {
+    pub since: String,
+    pub previous_output: FormOutPoint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormScript {
+    pub code_hash: String,
+    pub hash_type: String,
+    pub args: String,
+}
+
+impl FormScript {
+    fn to_molecule(&self) -> UResult<Vec<u8>> {
+        let hash_type = match self.hash_type.as_str() {
+            "data" => 0u8,
+            "type" => 1,
+            "data1" => 2,
+            "data2" => 4,
+            other => return Err(format_err!("unknown hash_type: {}", other)),
+        };
+        Ok(dynvec(&[parse_byte32(&self.code_hash)?.to_vec(), vec![hash_type], bytes(&parse_bytes(&self.args)?)]))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormCellOutput {
+    pub capacity: String,
+    pub lock: FormScript,
+    #[serde(rename = "type")]
+    pub type_: Option<FormScript>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormTransaction {
+    pub version: String,
+    pub cell_deps: Vec<FormCellDep>,
+    pub header_deps: Vec<String>,
+    pub inputs: Vec<FormCellInput>,
+    pub outputs: Vec<FormCellOutput>,
+    pub outputs_data: Vec<String>,
+    pub witnesses: Vec<String>,
+}
+
+impl FormTransaction {
+    // the RawTransaction, the transaction without witnesses
+    fn raw(&self) -> UResult<Vec<u8>> {
+        let mut cell_deps = vec![];
+        for dep in &self.cell_deps {
+            cell_deps.extend(dep.out_point.to_molecule()?);
+            cell_deps.push(match dep.dep_type.as_str() {
+                "code" => 0,
+                "dep_group" => 1,
+                other => return Err(format_err!("unknown dep_type: {}", other)),
+            });
+        }
+        let mut header_deps = vec![];
+        for hash in &self.header_deps {
+            header_deps.extend_from_slice(&parse_byte32(hash)?);
         }
+        let mut inputs = vec![];
+        for input in &self.inputs {
+            inputs.extend_from_slice(&(parse_uint(&input.since)? as u64).to_le_bytes());
+            inputs.extend(input.previous_output.to_molecule()?);
+        }
+        let mut outputs = vec![];
+        for output in &self.outputs {
+            let type_ = output.type_.as_ref().map(|t| t.to_molecule()).transpose()?.unwrap_or_default();
+            outputs.push(dynvec(&[(parse_uint(&output.capacity)? as u64).to_le_bytes().to_vec(), output.lock.to_molecule()?, type_]));
+        }
+        let outputs_data = self.outputs_data.iter().map(|d| parse_bytes(d).map(|d| bytes(&d))).collect::<UResult<Vec<_>>>()?;
+
+        Ok(dynvec(&[
+            (parse_uint(&self.version)? as u32).to_le_bytes().to_vec(),
+            fixvec(self.cell_deps.len(), &cell_deps),
+            fixvec(self.header_deps.len(), &header_deps),
+            fixvec(self.inputs.len(), &inputs),
+            dynvec(&outputs),
+            dynvec(&outputs_data),
+        ]))
     }
+    // (hash, witness hash)
+    pub fn hashes(&self) -> UResult<(Byte32, Byte32)> {
+        let raw = self.raw()?;
+        let witnesses = self.witnesses.iter().map(|w| parse_bytes(w).map(|w| bytes(&w))).collect::<UResult<Vec<_>>>()?;
+        let tx = dynvec(&[raw.clone(), dynvec(&witnesses)]);
+        Ok((ckb_hash::blake2b_256(raw), ckb_hash::blake2b_256(tx)))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormUncle {
+    pub header: FormHeader,
+    pub proposals: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FormTemplateTx {
+    pub data: FormTransaction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FormTemplate {
+    pub version: String,
+    pub compact_target: String,
+    pub current_time: String,
+    pub number: String,
+    pub epoch: String,
+    pub parent_hash: String,
+    pub uncles: Vec<FormUncle>,
+    pub transactions: Vec<FormTemplateTx>,
+    pub proposals: Vec<String>,
+    pub cellbase: FormTemplateTx,
+    pub work_id: String,
+    pub dao: String,
+    #[serde(default)]
+    pub extension: Option<String>,
+}
+
+// the block of submit_block, and the work_id of its template
+#[derive(Debug, Clone, Serialize)]
+pub struct FormBlock {
+    #[serde(skip)]
+    pub work_id: String,
+    pub header: FormHeader,
+    pub uncles: Vec<FormUncle>,
+    pub transactions: Vec<FormTransaction>,
+    pub proposals: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension: Option<String>,
+}
+
+// {"jsonrpc":"2.0","id":3,"method":"get_block_template","params":[null,null,null]}
+pub fn make_template() -> Req {
+    Request::new(atomic_id(), METHOD_GET_TEMPLATE, ((), (), ())).jsonrpc().into_req()
+}
+
+// the target of compact_target has no sign bit as the nbits of btc
+pub fn compact_to_target(compact: u32) -> H256 {
+    let (exponent, mantissa) = (compact >> 24, U256::from(compact & 0x00ff_ffff));
+    let target = if exponent <= 3 { mantissa >> (8 * (3 - exponent)) } else { mantissa << (8 * (exponent - 3)) };
+    H256::from_uint(&target)
+}
+
+// the roots of the header are computed from the cellbase, the transactions, the proposals and the uncles
+pub fn parse_template(form: &FormTemplate) -> UResult<Job> {
+    let transactions = std::iter::once(&form.cellbase).chain(&form.transactions).map(|tx| tx.data.clone()).collect::<Vec<_>>();
+    let (hashes, witness_hashes): (Vec<_>, Vec<_>) = transactions.iter().map(|tx| tx.hashes()).collect::<UResult<Vec<_>>>()?.into_iter().unzip();
+    let proposals = form.proposals.iter().map(|p| parse_bytes(p)).collect::<UResult<Vec<_>>>()?;
+    let uncles = form.uncles.iter().map(|u| u.header.to_header().map(|h| h.hash())).collect::<UResult<Vec<_>>>()?;
+    let extension = form.extension.as_deref().map(parse_bytes).transpose()?;
+
+    let header = Header {
+        version: parse_uint(&form.version)? as _,
+        compact_target: parse_uint(&form.compact_target)? as _,
+        timestamp: parse_uint(&form.current_time)? as _,
+        number: parse_uint(&form.number)? as _,
+        epoch: parse_uint(&form.epoch)? as _,
+        parent_hash: parse_byte32(&form.parent_hash)?,
+        transactions_root: transactions_root(&hashes, &witness_hashes),
+        proposals_hash: hash_concat(&proposals),
+        extra_hash: extra_hash(&uncles, extension.as_deref()),
+        dao: parse_byte32(&form.dao)?,
+        nonce: 0,
+    };
+
+    Ok(Job {
+        id: 0,
+        jobid: format!("{}:{}", header.number, clean_0x(&form.work_id)),
+        powhash: hex_string(&header.pow_hash()),
+        target: compact_to_target(header.compact_target),
+        nonce: 0,
+        height: header.number,
+        nonce1_bytes: 0,
+        block: Some(Arc::new(FormBlock {
+            work_id: form.work_id.clone(),
+            header: FormHeader::from_header(&header),
+            uncles: form.uncles.clone(),
+            transactions,
+            proposals: form.proposals.clone(),
+            extension: form.extension.clone(),
+        })),
+    })
+}
+
+// the eaglesong hashes the nonce in the big-endian bytes, which are the little-endian of the header nonce
+// {"jsonrpc":"2.0","id":9,"method":"submit_block","params":["0x0",{"header":{..},"uncles":[],"transactions":[..],"proposals":[]}]}
+// {"jsonrpc":"2.0","result":"0x<block hash>","id":9}
+fn make_submit_block(solution: &Solution, block: &FormBlock) -> Req {
+    let mut block = block.clone();
+    block.header.nonce = format!("{:#x}", solution.nonce.swap_bytes());
+    Request::new(solution.id, METHOD_SUBMIT_BLOCK, (&block.work_id, &block)).jsonrpc().into_req()
+}
+
+#[test]
+fn test_transaction_hash() {
+    // the example of get_transaction in the rpc doc of ckb
+    let tx = r#"{
+        "cell_deps":[{"dep_type":"code","out_point":{"index":"0x0","tx_hash":"0xa4037a893eb48e18ed4ef61034ce26eba9c585f15c9cee102ae58505565eccc3"}}],
+        "header_deps":["0x7978ec7ce5b507cfb52e149e36b1a23f6062ed150503c85bbf825da3599095ed"],
+        "inputs":[{"previous_output":{"index":"0x0","tx_hash":"0x365698b50ca0da75dca2c87f9e7b563811d3b5813736b8cc62cc3b106faceb17"},"since":"0x0"}],
+        "outputs":[{"capacity":"0x2540be400","lock":{"code_hash":"0x28e83a1277d48add8e72fadaa9248559e1b632bab2bd60b27955ebc4c03800a5","hash_type":"data","args":"0x"},"type":null}],
+        "outputs_data":["0x"],
+        "version":"0x0",
+        "witnesses":[]
+    }"#;
+    let tx = serde_json::from_str::<FormTransaction>(tx).unwrap();
+    let (hash, _) = tx.hashes().unwrap();
+    assert_eq!(hex_string(&hash), "a0ef4eb5f4ceeb08a4c8524d84c5da95dce2f608e0ca2ec8091191b0f330c6e3");
+
+    // the example of get_header
+    let header = r#"{
+        "compact_target":"0x1e083126",
+        "dao":"0xb5a3e047474401001bc476b9ee573000c0c387962a38000000febffacf030000",
+        "epoch":"0x7080018000001",
+        "extra_hash":"0x0000000000000000000000000000000000000000000000000000000000000000",
+        "nonce":"0x0",
+        "number":"0x400",
+        "parent_hash":"0xae003585fa15309b30b31aed3dcf385e9472c3c3e93746a6c4540629a6a1ed2d",
+        "proposals_hash":"0x0000000000000000000000000000000000000000000000000000000000000000",
+        "timestamp":"0x5cd2b117",
+        "transactions_root":"0xc47d5b78b3c4c4c853e2a32810818940d0ee403423bea9ec7b8e566d9595206c",
+        "version":"0x0"
+    }"#;
+    let form = serde_json::from_str::<FormHeader>(header).unwrap();
+    let header = form.to_header().unwrap();
+    assert_eq!(hex_string(&header.hash()), "a5f5c85987a15de25661e5a214f2c1449cd803f071acc7999820f25246471f40");
+    assert_eq!(FormHeader::from_header(&header), form);
+}
+
+#[test]
+fn test_block_template() {
+    use super::molecule::merkle_root;
+    use super::pow::Computer;
+
+    let tx = r#"{"version":"0x0","cell_deps":[],"header_deps":[],"inputs":[{"previous_output":{"index":"0xffffffff","tx_hash":"0x0000000000000000000000000000000000000000000000000000000000000000"},"since":"0x401"}],
+        "outputs":[{"capacity":"0x18e64efc04","lock":{"code_hash":"0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8","hash_type":"type","args":"0xb2e61ff569acf041b3c2c17724e2379c581eeac3"},"type":null}],
+        "outputs_data":["0x"],"witnesses":["0x5000000010000000300000003100000028e83a1277d48add8e72fadaa9248559e1b632bab2bd60b27955ebc4c03800a5000000000000000000"]}"#;
+    let uncle = r#"{"hash":"0xa5f5c85987a15de25661e5a214f2c1449cd803f071acc7999820f25246471f40","required":false,"proposals":[],"header":{"compact_target":"0x1e083126","dao":"0xb5a3e047474401001bc476b9ee573000c0c387962a38000000febffacf030000","epoch":"0x7080018000001","extra_hash":"0x0000000000000000000000000000000000000000000000000000000000000000","nonce":"0x0","number":"0x400","parent_hash":"0xae003585fa15309b30b31aed3dcf385e9472c3c3e93746a6c4540629a6a1ed2d","proposals_hash":"0x0000000000000000000000000000000000000000000000000000000000000000","timestamp":"0x5cd2b117","transactions_root":"0xc47d5b78b3c4c4c853e2a32810818940d0ee403423bea9ec7b8e566d9595206c","version":"0x0"}}"#;
+    let template = format!(
+        r#"{{"jsonrpc":"2.0","id":3,"result":{{"version":"0x0","compact_target":"0x20ffffff","current_time":"0x174c45e17a3","number":"0x401","epoch":"0x7080019000001","parent_hash":"0xa5f5c85987a15de25661e5a214f2c1449cd803f071acc7999820f25246471f40",
+        "cycles_limit":"0xd09dc300","bytes_limit":"0x91c08","uncles_count_limit":"0x2","uncles":[{}],"transactions":[],"proposals":["0xa0ef4eb5f4ceeb08a4c8"],
+        "cellbase":{{"hash":"0x00","cycles":null,"data":{}}},"work_id":"0x2a","dao":"0xb5a3e047474401001bc476b9ee573000c0c387962a38000000febffacf030000","extension":"0x0102"}}}}"#,
+        uncle, tx
+    );
+    let form = match crate::stratum::parse(&template).unwrap() {
+        crate::stratum::Message::Response(r) => r.result::<FormTemplate>().unwrap(),
+        m => panic!("{:?}", m),
+    };
+    let mut job = parse_template(&form).unwrap();
+    assert_eq!((job.height, job.jobid.as_str()), (0x401, "1025:2a"));
+    assert_eq!(job.target, compact_to_target(0x20ffffff));
+
+    let block = job.block.clone().unwrap();
+    let header = block.header.to_header().unwrap();
+    let (hash, witness_hash) = form.cellbase.data.hashes().unwrap();
+    assert_eq!(header.transactions_root, merkle_root(&[hash, witness_hash]));
+    assert_eq!(header.proposals_hash, ckb_hash::blake2b_256(parse_bytes("0xa0ef4eb5f4ceeb08a4c8").unwrap()));
+    let uncle_hash = parse_byte32("0xa5f5c85987a15de25661e5a214f2c1449cd803f071acc7999820f25246471f40").unwrap();
+    assert_eq!(header.extra_hash, extra_hash(&[uncle_hash], Some(&[1, 2])));
+    assert_eq!((header.timestamp, header.number), (0x174c45e17a3, 0x401));
+
+    let mut computer = Computer::new(false);
+    computer.update(&job.powhash);
+    job.nonce = 0x0102030405060708090a0b0c0d0e0f10;
+    let solution = (job.nonce..).find_map(|n| computer.compute(&job, n)).unwrap();
+    let req = make_submit(&solution, &job).unwrap();
+    assert_eq!(req.1, METHOD_SUBMIT_BLOCK);
+
+    // the node hashes the pow hash of header with the nonce(u128 le)
+    let submit = serde_json::from_str::<Value>(&req.2).unwrap();
+    assert_eq!(submit["params"][0], "0x2a");
+    let block = serde_json::from_value::<Value>(submit["params"][1].clone()).unwrap();
+    let mut header = serde_json::from_value::<FormHeader>(block["header"].clone()).unwrap().to_header().unwrap();
+    let mut message = header.pow_hash().to_vec();
+    message.extend_from_slice(&header.nonce.to_le_bytes());
+    let mut output = [0u8; 32];
+    eaglesong::eaglesong(&message, &mut output);
+    assert!(H256::from(output) <= job.target);
+    header.nonce = 0;
+    assert_eq!(hex_string(&header.pow_hash()), job.powhash);
+
+    assert_eq!(block["uncles"][0].as_object().unwrap().len(), 2);
+    assert_eq!(block["transactions"].as_array().unwrap().len(), 1);
+    assert_eq!((&block["proposals"][0], &block["extension"]), (&Value::from("0xa0ef4eb5f4ceeb08a4c8"), &Value::from("0x0102")));
 }
@@ -0,0 +1,143 @@
+// the full DAGs and the caches are saved as {dir}/ethash-{chain}-{epoch}.{dag,cache} and loaded by mmap,
+// so the restarts skip the generation and the processes on a host share the page cache
+use memmap2::Mmap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"CMDAG\0\0\x01";
+// magic, the size(u64 le) and the crc32(u32 le) of the dataset, padding
+const HEADER_LEN: usize = 32;
+
+pub struct DagFile {
+    mmap: Mmap,
+}
+
+impl DagFile {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.mmap[HEADER_LEN..]
+    }
+}
+
+// $XDG_CACHE_HOME/cminer, ~/.cache/cminer or $TMPDIR/cminer
+pub fn default_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").filter(|d| !d.is_empty()).map(|h| Path::new(&h).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("cminer")
+}
+
+// the kind is dag or cache
+pub fn path(dir: &Path, chain: &str, epoch: usize, kind: &str) -> PathBuf {
+    dir.join(format!("ethash-{}-{}.{}", chain, epoch, kind))
+}
+
+fn make_header(dataset: &[u8]) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[..8].copy_from_slice(MAGIC);
+    header[8..16].copy_from_slice(&(dataset.len() as u64).to_le_bytes());
+    header[16..20].copy_from_slice(&crc32fast::hash(dataset).to_le_bytes());
+    header
+}
+
+fn invalid(path: &Path, desc: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{} {}", path.display(), desc))
+}
+
+// the checksum reads the whole file, the pages are needed by the workers anyway
+pub fn load(path: &Path, size: usize) -> io::Result<DagFile> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; HEADER_LEN];
+    file.read_exact(&mut header).map_err(|_| invalid(path, "is truncated"))?;
+    if &header[..8] != MAGIC {
+        return Err(invalid(path, "isn't a dag file"));
+    }
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&header[8..16]);
+    let len = u64::from_le_bytes(buf);
+    if len != size as u64 || file.metadata()?.len() != (HEADER_LEN + size) as u64 {
+        return Err(invalid(path, &format!("has a wrong size {}, expect {}", len, size)));
+    }
+
+    let mmap = unsafe { Mmap::map(&file)? };
+    let dag = DagFile { mmap };
+    if dag.mmap[..HEADER_LEN] != make_header(dag.as_bytes())[..] {
+        return Err(invalid(path, "has a wrong checksum"));
+    }
+    // the dataset is accessed randomly by hashimoto
+    #[cfg(unix)]
+    dag.mmap.advise(memmap2::Advice::Random).ok();
+    Ok(dag)
+}
+
+// written to a temporary file then renamed, the readers never see a partial dag
+pub fn save(path: &Path, dataset: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    let write = || -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        file.write_all(&make_header(dataset))?;
+        file.write_all(dataset)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, path)
+    };
+    write().inspect_err(|_| {
+        fs::remove_file(&tmp).ok();
+    })
+}
+
+// the dags of the chain are several GB each, only the epoch and its neighbours are kept
+pub fn remove_old(dir: &Path, chain: &str, epoch: usize) {
+    let prefix = format!("ethash-{}-", chain);
+    let entries = match fs::read_dir(dir) {
+        Ok(es) => es,
+        Err(_) => return,
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let old = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix(&prefix))
+            .and_then(|n| n.split_once('.'))
+            .filter(|(_, kind)| ["dag", "cache"].contains(kind))
+            .and_then(|(e, _)| e.parse::<usize>().ok())
+            .filter(|e| e + 1 < epoch || *e > epoch + 1);
+        if let Some(old) = old {
+            match fs::remove_file(&path) {
+                Ok(_) => info!("removed the dag of {} epoch {}: {}", chain, old, path.display()),
+                Err(e) => warn!("remove the dag {} failed: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_dag_file() {
+    let dir = std::env::temp_dir().join(format!("cminer-test-dag-{}", std::process::id()));
+    let dataset = (0..4096u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+    let p = path(&dir, "etc", 3, "dag");
+    assert!(load(&p, dataset.len()).is_err());
+
+    save(&p, &dataset).unwrap();
+    assert_eq!(load(&p, dataset.len()).unwrap().as_bytes(), &dataset[..]);
+    assert!(load(&p, dataset.len() + 64).is_err());
+
+    let mut bytes = fs::read(&p).unwrap();
+    bytes[HEADER_LEN + 100] ^= 1;
+    fs::write(&p, bytes).unwrap();
+    assert!(load(&p, dataset.len()).is_err());
+
+    save(&path(&dir, "etc", 1, "dag"), &dataset).unwrap();
+    save(&path(&dir, "etc", 1, "cache"), &dataset).unwrap();
+    save(&path(&dir, "eth", 1, "dag"), &dataset).unwrap();
+    remove_old(&dir, "etc", 3);
+    assert!(p.exists() && !path(&dir, "etc", 1, "dag").exists() && !path(&dir, "etc", 1, "cache").exists());
+    assert!(path(&dir, "eth", 1, "dag").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
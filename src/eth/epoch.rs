@@ -0,0 +1,197 @@
+// the dag of the next epoch is pregenerated on a low priority pool before the boundary,
+// so the workers switch to it at once when the job of the new epoch comes
+use bytesize::ByteSize;
+use parking_lot::{Condvar, Mutex};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::{mem, thread};
+
+use crate::chain::Chain;
+use crate::config::Config;
+use crate::eth::dag;
+use crate::eth::pow::Computer;
+
+type Slot = Arc<(Mutex<Option<Computer>>, Condvar)>;
+
+#[derive(Debug)]
+pub struct Dags {
+    workers: usize,
+    chain: Chain,
+    light: bool,
+    dag_dir: PathBuf,
+    ahead: usize,
+    memory: Option<u64>,
+    pool: OnceLock<ThreadPool>,
+    // the pregenerating or pregenerated epoch, None for the epoch skipped by the memory limit
+    next: Mutex<Option<(usize, Option<Slot>)>>,
+    // the epoch of the jobs waiting for the dag
+    generating: Mutex<Option<usize>>,
+}
+
+impl Dags {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            workers: config.workers,
+            chain: config.chain(),
+            light: config.light,
+            dag_dir: config.dag_dir.clone().unwrap_or_else(dag::default_dir),
+            ahead: config.dag_ahead,
+            memory: config.dag_memory.as_deref().map(|m| m.parse::<ByteSize>().expect("dag_memory checked by config").as_u64()),
+            pool: OnceLock::new(),
+            next: Mutex::new(None),
+            generating: Mutex::new(None),
+        }
+    }
+    // the pregenerated one without waiting
+    pub fn ready(&self, epoch: usize) -> Option<Computer> {
+        let mut next = self.next.lock();
+        match next.as_ref() {
+            Some((e, Some(slot))) if *e == epoch => {
+                let computer = slot.0.lock().take();
+                if computer.is_some() {
+                    next.take();
+                }
+                computer
+            }
+            _ => None,
+        }
+    }
+    // the dag is generated or loaded in a background thread, the net thread keeps talking with the pool meanwhile
+    pub fn generate<F>(self: &Arc<Self>, epoch: usize, done: F)
+    where
+        F: FnOnce(Computer) + Send + 'static,
+    {
+        {
+            let mut generating = self.generating.lock();
+            if *generating == Some(epoch) {
+                return;
+            }
+            *generating = Some(epoch);
+        }
+
+        let this = self.clone();
+        thread::Builder::new()
+            .name(format!("dag-{}", epoch))
+            .spawn(move || {
+                let computer = this.computer(epoch);
+                let mut generating = this.generating.lock();
+                if *generating == Some(epoch) {
+                    *generating = None;
+                }
+                mem::drop(generating);
+                done(computer)
+            })
+            .expect("spawn the dag thread");
+    }
+    // the pregenerated one if any, it waits for the pregenerating
+    pub fn computer(&self, epoch: usize) -> Computer {
+        let slot = {
+            let mut next = self.next.lock();
+            match next.as_ref() {
+                Some((e, Some(_))) if *e == epoch => next.take().and_then(|n| n.1),
+                _ => None,
+            }
+        };
+        let slot = match slot {
+            Some(slot) => slot,
+            None => return Computer::new(epoch, self.workers, self.chain, Some(&self.dag_dir), self.light),
+        };
+
+        let mut computer = slot.0.lock();
+        if computer.is_none() {
+            warn!("wait for the pregenerating dag of epoch {}", epoch);
+        }
+        while computer.is_none() {
+            slot.1.wait(&mut computer);
+        }
+        computer.take().expect("pregenerated computer")
+    }
+    // called by the jobs with the height, current is the dag size of the computing
+    pub fn prepare(&self, height: usize, current: usize) {
+        let (epoch, left) = self.chain.next_epoch(height);
+        if self.ahead == 0 || self.workers == 0 || left > self.ahead {
+            return;
+        }
+
+        let mut next = self.next.lock();
+        if next.as_ref().map(|n| n.0) == Some(epoch) {
+            return;
+        }
+        let dataset_epoch = self.chain.dataset_epoch(epoch);
+        let mut size = ethash::get_cache_size(dataset_epoch);
+        if !self.light {
+            size += ethash::get_full_size(dataset_epoch);
+        }
+        let limit = self.memory.or_else(mem_total);
+        if limit.map(|l| (current + size) as u64 > l).unwrap_or(true) {
+            warn!(
+                "skip pregenerating the dag of epoch {}, {} + {} exceeds the memory limit {:?}",
+                epoch,
+                ByteSize::b(current as _),
+                ByteSize::b(size as _),
+                limit.map(ByteSize::b)
+            );
+            *next = Some((epoch, None));
+            return;
+        }
+
+        warn!("pregenerate the dag of epoch {}, {} blocks before the boundary", epoch, left);
+        let slot: Slot = Default::default();
+        *next = Some((epoch, Some(slot.clone())));
+        let (workers, chain, light, dag_dir) = (self.workers, self.chain, self.light, self.dag_dir.clone());
+        self.pool().spawn(move || {
+            let computer = Computer::new(epoch, workers, chain, Some(&dag_dir), light);
+            *slot.0.lock() = Some(computer);
+            slot.1.notify_all();
+        });
+    }
+    // the threads are niced, the workers of the current epoch go first
+    fn pool(&self) -> &ThreadPool {
+        self.pool.get_or_init(|| {
+            ThreadPoolBuilder::new()
+                .thread_name(|i| format!("dag-pregen-{}", i))
+                .start_handler(|_| lower_priority())
+                .build()
+                .expect("build the dag pregenerating pool")
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lower_priority() {
+    // the nice of linux is per thread, 0 is the calling one
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+        warn!("lower the priority of dag pregenerating thread failed: {}", std::io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lower_priority() {}
+
+#[cfg(target_os = "linux")]
+fn mem_total() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kb = meminfo.lines().find_map(|l| l.strip_prefix("MemTotal:"))?.trim().trim_end_matches("kB").trim();
+    kb.parse::<u64>().ok().map(|kb| kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mem_total() -> Option<u64> {
+    None
+}
+
+#[test]
+fn test_dags_of_states() {
+    let mut config = Config::new2("eth", false, "127.0.0.1:3333", 1, "sp_yos", "cpux", 0);
+    config.dag_memory = Some("1KB".to_owned());
+    let limited = Dags::new(&config);
+    config.dag_ahead = 0;
+    let disabled = Dags::new(&config);
+
+    // 10 blocks before the epoch 2, the dag is skipped by the memory limit of its own config
+    limited.prepare(60000 - 10, 0);
+    assert!(matches!(*limited.next.lock(), Some((2, None))));
+    disabled.prepare(60000 - 10, 0);
+    assert!(disabled.next.lock().is_none());
+}
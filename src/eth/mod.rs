@@ -1,27 +1,43 @@
+use bigint::H256;
 use serde_json;
-use std::mem;
+use std::{mem, sync::Arc};
 
+pub mod dag;
+pub mod epoch;
 pub mod pow;
 pub mod proto;
 
-use crate::config::TIMEOUT_SECS;
+use crate::config::{Config, EthDialect, TIMEOUT_SECS};
 use crate::state::{Handle, Handler, Job as JobID, Req, Run, State, Worker};
-use crate::util::{self, target_to_difficulty};
+use crate::stratum::{self, Message};
+use crate::util::{self, target_to_difficulty, LoginError};
 
+use epoch::Dags;
 use pow::Computer;
-use proto::{make_hashrate, make_login, make_submit, FormJob, FormResult, Job, METHOD_SUBMIT_WORK};
+use proto::{
+    make_authorize, make_getwork, make_hashrate, make_login, make_proxy_login, make_submit, parse_notify, parse_subscribe, Extranonce, FormJob, Job, MethodParams, METHOD_AUTHORIZE, METHOD_LOGIN,
+    METHOD_SET_DIFFICULTY, METHOD_SUBMIT, METHOD_SUBMIT_WORK, METHOD_SUBSCRIBE,
+};
 
 #[derive(Debug, Clone)]
 pub enum EthJob {
     Compute((Computer, Job)),
+    // waiting for the dag
+    Pending(Job),
+    // the extranonce and the target of EthereumStratum before the first job
+    Nonce1t((Extranonce, H256)),
     Sleep,
     Exit,
 }
 
 impl JobID for EthJob {
+    type Shared = Arc<Dags>;
+    fn shared(config: &Config) -> Self::Shared {
+        Arc::new(Dags::new(config))
+    }
     fn jobid(&self) -> String {
         match &self {
-            Self::Compute((_, job)) => format!("{:?}", job.powhash),
+            Self::Compute((_, job)) | Self::Pending(job) => format!("{:?}", job.powhash),
             _ => "0".to_owned(),
         }
     }
@@ -33,18 +49,140 @@ impl Default for EthJob {
     }
 }
 
+impl State<EthJob> {
+    // the latest job waits for the dag of its epoch
+    fn computer_ready(&self, epoch: usize, computer: Computer) {
+        let mut lock = self.value().lock();
+        let lock = &mut *lock;
+        match mem::replace(&mut lock.job, EthJob::Sleep) {
+            EthJob::Pending(j) if j.epoch == epoch => {
+                warn!("the dag of epoch {} is ready, {:?}", epoch, computer);
+                lock.job = EthJob::Compute((computer, j));
+                lock.jobsc.add_slow(1);
+            }
+            js => {
+                info!("drop the dag of epoch {}, the job: {}", epoch, js.jobid());
+                lock.job = js;
+            }
+        }
+    }
+    // the job of eth-proxy or EthereumStratum, the workers keep the computer of the same epoch
+    fn update_job(&self, mut j: Job, reqid: Option<usize>) {
+        let height = j.height;
+        let mut lock = self.value().lock();
+        let lock = &mut *lock;
+        // the response of eth_getWork
+        if let Some(id) = reqid {
+            lock.reqs.remove(id);
+        }
+        // the node answers the polling with the same work until the next block
+        if let EthJob::Compute((_, oj)) | EthJob::Pending(oj) = &lock.job {
+            if reqid.is_some() && oj.jobid.is_none() && (oj.powhash, oj.target) == (j.powhash, j.target) {
+                return;
+            }
+        }
+        info!(
+            "job: {}, epoch: {}, seedhash: {:?}, diff: {}, nonce: {:0x}",
+            j.powhash,
+            j.epoch,
+            j.seedhash.map(|h| h.to_string()),
+            target_to_difficulty(&j.target),
+            j.nonce
+        );
+        j.id = lock.jobsc.get() + 1;
+
+        let dags = self.shared();
+        let epoch = j.epoch;
+        lock.job = match mem::replace(&mut lock.job, EthJob::Sleep) {
+            EthJob::Compute((oc, oj)) if j.epoch == oj.epoch => EthJob::Compute((oc, j)),
+            EthJob::Pending(oj) if j.epoch == oj.epoch => EthJob::Pending(j),
+            EthJob::Exit => EthJob::Exit,
+            // the old dag is dropped before generating the new one
+            _ => match dags.ready(epoch) {
+                Some(c) => EthJob::Compute((c, j)),
+                None => {
+                    let state = self.clone();
+                    dags.generate(epoch, move |c| state.computer_ready(epoch, c));
+                    EthJob::Pending(j)
+                }
+            },
+        };
+        lock.jobsc.add_slow(1);
+
+        if let (Some(height), EthJob::Compute((c, _))) = (height, &lock.job) {
+            dags.prepare(height, c.size());
+        }
+    }
+    // the target and the extranonce of EthereumStratum are kept by the job
+    fn handle_notify(&self, mp: MethodParams) {
+        let mut guard = self.value().lock();
+        let lock = &mut *guard;
+        let (extranonce, target) = match &lock.job {
+            EthJob::Compute((_, j)) | EthJob::Pending(j) => (j.extranonce, j.target),
+            EthJob::Nonce1t(nt) => *nt,
+            _ => Default::default(),
+        };
+
+        match mp {
+            MethodParams::Job(mut j) => {
+                if target.is_zero() {
+                    warn!("the job {:?} arrived before {}", j.jobid, METHOD_SET_DIFFICULTY);
+                }
+                j.target = target;
+                j.extranonce = extranonce;
+                j.nonce = extranonce.start_nonce().into();
+                mem::drop(guard);
+                self.update_job(j, None);
+            }
+            MethodParams::Target(target) => match &mut lock.job {
+                // the workers clone the job, they take the new target by the next one
+                EthJob::Compute((_, j)) | EthJob::Pending(j) => {
+                    j.target = target;
+                    lock.jobsc.add_slow(1);
+                }
+                EthJob::Exit => {}
+                js => *js = EthJob::Nonce1t((extranonce, target)),
+            },
+            MethodParams::Extranonce(extranonce) => match &mut lock.job {
+                EthJob::Compute((_, j)) | EthJob::Pending(j) => {
+                    j.extranonce = extranonce;
+                    j.nonce = extranonce.start_nonce().into();
+                    lock.jobsc.add_slow(1);
+                }
+                EthJob::Exit => {}
+                js => *js = EthJob::Nonce1t((extranonce, target)),
+            },
+        }
+    }
+}
+
 impl Handle for State<EthJob> {
-    fn login_request(&self) -> Req {
-        make_login(&self.config())
+    // the node of http(s):// pool has no login, such as geth
+    fn login_request(&self) -> Vec<Req> {
+        if self.pool().addr.scheme.is_http() {
+            return vec![make_getwork()];
+        }
+        make_login(self.pool(), self.config().eth_dialect)
     }
+    // EthereumStratum has no hashrate method
     fn hashrate_request(&self, hashrate: u64) -> Option<Req> {
-        Some(make_hashrate(hashrate))
+        let stratum = match &self.value().lock().job {
+            EthJob::Compute((_, j)) | EthJob::Pending(j) => j.jobid.is_some(),
+            EthJob::Nonce1t(_) => true,
+            _ => self.config().eth_dialect == EthDialect::Stratum,
+        };
+        Some(make_hashrate(hashrate)).filter(|_| !stratum)
+    }
+    fn poll_request(&self) -> Option<Req> {
+        Some(make_getwork()).filter(|_| self.pool().addr.scheme.is_http())
     }
     fn handle_request(&self, req: Req) -> util::Result<String> {
         let mut lock = self.value().lock();
-        lock.reqs.add(&req);
+        if req.1 != stratum::METHOD_REPLY {
+            lock.reqs.add(&req);
+        }
 
-        if req.1 == METHOD_SUBMIT_WORK {
+        if req.1 == METHOD_SUBMIT_WORK || req.1 == METHOD_SUBMIT {
             (*lock).submitc += 1;
         }
         trace!("id: {}, method: {}, req: {}", req.0, req.1, req.2);
@@ -53,69 +191,66 @@ impl Handle for State<EthJob> {
     fn handle_response(&self, resp: String) -> util::Result<()> {
         trace!("resp: {}", resp);
 
-        if let Ok(jf) = serde_json::from_str::<FormJob>(&resp) {
-            match jf.to_job() {
-                Ok(mut j) => {
-                    info!(
-                        "job: {}, epoch: {}, seedhash: {:?}, diff: {}, nonce: {:0x}",
-                        j.powhash,
-                        j.epoch,
-                        j.seedhash.map(|h| h.to_string()),
-                        target_to_difficulty(&j.target),
-                        j.nonce
-                    );
-                    let mut epoch_is_old = true;
-                    let mut lock = self.value().lock();
-                    let lock = &mut *lock;
-                    j.id = lock.jobsc.get() + 1;
-
-                    let js = match mem::replace(&mut lock.job, EthJob::Sleep) {
-                        EthJob::Compute((oc, oj)) => {
-                            if j.epoch == oj.epoch {
-                                EthJob::Compute((oc, j))
-                            } else {
-                                mem::drop(oc);
-                                epoch_is_old = false;
-                                lock.jobsc.add_slow(1);
-                                let c = Computer::new(j.epoch, self.config().workers, self.config().testnet);
-                                EthJob::Compute((c, j))
-                            }
-                        }
-                        EthJob::Sleep => {
-                            epoch_is_old = false;
-                            lock.jobsc.add_slow(1);
-                            let c = Computer::new(j.epoch, self.config().workers, self.config().testnet);
-                            EthJob::Compute((c, j))
-                        }
-                        EthJob::Exit => return Ok(()),
-                    };
-
-                    lock.job = js;
-                    if epoch_is_old {
-                        lock.jobsc.add_slow(1);
-                    }
+        // eth-proxy pushes the jobs as the responses, EthereumStratum pushes them by mining.notify
+        let msg = stratum::parse(&resp);
+        if let Ok(Message::Notify(notify)) = msg {
+            if let Some(notify) = stratum::handle_notify(self, notify)? {
+                let method = notify.method.clone();
+                match parse_notify(notify) {
+                    Ok(mp) => self.handle_notify(mp),
+                    Err(e) => warn!("ignore the notify {}: {}", method, e),
                 }
+            }
+        } else if let Ok(jf) = serde_json::from_str::<FormJob>(&resp) {
+            match jf.to_job(self.config().chain()) {
+                Ok(j) => self.update_job(j, Some(jf.id)),
                 Err(e) => error!("handle job({:?}) error: {}", jf.result, e),
             }
-        } else if let Ok(FormResult { id, result, error }) = serde_json::from_str(&resp) {
-            let mut lock = self.value().lock();
-            let lock = &mut *lock;
+        } else if let Ok(Message::Response(rf)) = msg {
+            let result = rf.to_bool();
+            let error = rf.error_string();
+            let mut guard = self.value().lock();
+            let lock = &mut *guard;
 
-            if let Some(req) = lock.reqs.remove(id) {
+            if let Some(req) = lock.reqs.remove(rf.id) {
                 let costed = req.time.elapsed();
-                if req.method == METHOD_SUBMIT_WORK {
-                    if result {
+                if req.method == METHOD_SUBSCRIBE {
+                    match parse_subscribe(&rf) {
+                        Ok(extranonce) => {
+                            info!("{} {:?}: {:x}, {}bytes", req.method, costed, extranonce.prefix, extranonce.bytes);
+                            mem::drop(guard);
+                            self.handle_notify(MethodParams::Extranonce(extranonce));
+                            if self.config().eth_dialect == EthDialect::Auto {
+                                self.sender()
+                                    .clone()
+                                    .try_send(Ok(make_authorize(self.pool())))
+                                    .map_err(|e| error!("try send authorize failed: {:?}", e))
+                                    .ok();
+                            }
+                        }
+                        Err(e) if self.config().eth_dialect == EthDialect::Auto => {
+                            warn!("{} failed: {}, error: {:?}, fall back to eth-proxy", req.method, e, error);
+                            for req in make_proxy_login(self.pool()) {
+                                self.sender().clone().try_send(Ok(req)).map_err(|e| error!("try send login failed: {:?}", e)).ok();
+                            }
+                        }
+                        Err(e) => return Err(LoginError::new(req.method, self.pool(), error.or_else(|| Some(e.to_owned()))).into()),
+                    }
+                } else if req.method == METHOD_SUBMIT_WORK || req.method == METHOD_SUBMIT {
+                    if result == Some(true) {
                         lock.acceptc += 1;
-                        info!("submit {} accepted {:?}", id, costed);
+                        info!("submit {} accepted {:?}", rf.id, costed);
                     } else {
                         lock.rejectc += 1;
-                        error!("submit {} rejected {:?}, error: {:?}", id, costed, error);
+                        error!("submit {} rejected {:?}, error: {:?}", rf.id, costed, error);
                     }
+                } else if (req.method == METHOD_LOGIN || req.method == METHOD_AUTHORIZE) && result != Some(true) {
+                    return Err(LoginError::new(req.method, self.pool(), error).into());
                 } else {
-                    info!("request {}#{} {:?}, error: {:?}", id, req.method, costed, error);
+                    info!("request {}#{} {:?}, error: {:?}", rf.id, req.method, costed, error);
                 }
             } else {
-                warn!("unkown response id: {}, result: {}, error: {:?}", id, result, error);
+                warn!("unkown response id: {}, result: {}, error: {:?}", rf.id, rf.result, error);
             }
         } else {
             error!("unkown resp: {}", resp);
@@ -148,7 +283,7 @@ impl Run for Worker<EthJob> {
                         nonce = c.1.nonce + self.idx;
                         compute = Some(c);
                     }
-                    EthJob::Sleep => compute = None,
+                    EthJob::Pending(_) | EthJob::Nonce1t(_) | EthJob::Sleep => compute = None,
                     EthJob::Exit => break,
                 }
             }
@@ -156,7 +291,11 @@ impl Run for Worker<EthJob> {
             if let Some((c, j)) = compute.as_ref() {
                 if let Some(s) = c.compute(j, &nonce) {
                     warn!("found a solution: id: {}, nonce: {:0x}, powhash: {}, diff: {}", s.id, nonce, j.powhash, target_to_difficulty(&s.target));
-                    make_submit(&s, j).map(|req| self.sender.try_send(Ok(req)).map_err(|e| error!("try send solution error: {:?}", e)).ok());
+                    if c.verify(j, &s) {
+                        make_submit(&s, j, self.job.pool()).map(|req| self.sender.try_send(Ok(req)).map_err(|e| error!("try send solution error: {:?}", e)).ok());
+                    } else {
+                        error!("drop the solution {} failed the light verification, the dag of epoch {} may be broken", s.id, j.epoch);
+                    }
                     util::sleep_secs(self.sleep);
                 }
                 self.hashrate.add(1);
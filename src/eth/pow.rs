@@ -1,18 +1,22 @@
 use bigint::{BigEndianHash, H256, H64, U64};
 use bytesize::ByteSize;
 use rayon::current_num_threads;
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
 
+use crate::chain::Chain;
+use crate::eth::dag::{self, DagFile};
 use crate::eth::proto::{FormJob, Job, Solution};
 use crate::util::{atomic_id, target_to_difficulty};
 
 pub fn fun() {
     let notify = r#"{"id":0,"jsonrpc":"2.0","result":["0x93cca7a948af373321f5ba7a5de6b51d60348afd86063fbddd7dc4e553560798","0x1a7d0730fc4d6e634f5506e6530175aaea40fddd86fa7d41af81ef34f7293b09","0x000001ad7f29abcaf485787a6520ec08d23699194119a5c37387b71906614310"]}"#;
     let jobform: FormJob = serde_json::from_str(notify).unwrap();
-    let job = jobform.to_job().unwrap();
+    let job = jobform.to_job(Chain::Eth).unwrap();
 
     info!("epoch: {}", job.epoch);
-    let computer = Computer::new(job.epoch, 1, false);
+    let computer = Computer::new(job.epoch, 1, Chain::Eth, None, false);
 
     let now = std::time::Instant::now();
     let mut nonce = 0.into();
@@ -81,10 +85,27 @@ impl FullBytes {
     }
 }
 
+pub enum Dataset {
+    Bytes(Arc<FullBytes>),
+    File(DagFile),
+}
+
+impl Dataset {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Bytes(b) => b.as_bytes(),
+            Self::File(f) => f.as_bytes(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Computer {
     epoch: usize,
-    full: Arc<FullBytes>,
+    full_size: usize,
+    // empty in the light mode
+    full: Arc<Dataset>,
+    light: Arc<Dataset>,
 }
 
 use std::fmt;
@@ -95,46 +116,67 @@ impl fmt::Debug for Computer {
 }
 
 impl Computer {
-    // ethash epoch_length is 30000
-    // etchash epoch_length is 60000
-    pub fn new(mut epoch: usize, wokrers: usize, etc: bool) -> Self {
-        if etc {
-            epoch /= 2;
-        }
+    // the epoch is the seed epoch of the job, the dataset sizes are by the epoch of the chain
+    // the dag and the cache are loaded from or saved to the cache dir if any, the light mode only builds the cache
+    pub fn new(seed_epoch: usize, wokrers: usize, chain: Chain, dag_dir: Option<&Path>, light_mode: bool) -> Self {
+        let epoch = chain.dataset_epoch(seed_epoch);
         let light_size = ethash::get_cache_size(epoch);
         let full_size = ethash::get_full_size(epoch);
         warn!(
-            "Computer::new, epoch: {}, light: {}, full: {}, current_num_threads: {}",
+            "Computer::new, chain: {}, epoch: {}, light: {}, full: {}, light_mode: {}, current_num_threads: {}",
+            chain,
             epoch,
             ByteSize::b(light_size as _),
             ByteSize::b(full_size as _),
+            light_mode,
             current_num_threads()
         );
 
-        let mut full = Arc::from(FullBytes::new(0));
-        let seedhash = ethash::get_seedhash(if etc { epoch * 2 } else { epoch });
+        let mut full = Dataset::Bytes(Arc::new(FullBytes::new(0)));
+        let mut light = Arc::new(Dataset::Bytes(Arc::new(FullBytes::new(0))));
+        let seedhash = ethash::get_seedhash(seed_epoch);
+        let chain = chain.dag_key();
+        let cached = |kind: &str, size: usize, make: &dyn Fn() -> Arc<FullBytes>| {
+            let path = dag_dir.map(|d| dag::path(d, &chain, epoch, kind));
+            let dataset = load_or_make(path.as_deref(), size, make);
+            if let (Dataset::File(_), Some(dir)) = (&dataset, dag_dir) {
+                dag::remove_old(dir, &chain, epoch);
+            }
+            dataset
+        };
 
         if wokrers > 0 {
-            let mut light = vec![0; light_size];
-
-            ethash::make_cache(&mut light, seedhash);
-            let light = Arc::from(light);
-
-            full = Arc::from(FullBytes::new(full_size));
-            make_full(&full, &light);
-
+            light = Arc::new(cached("cache", light_size, &|| {
+                let light = Arc::new(FullBytes::new(light_size));
+                ethash::make_cache(light.as_mut_bytes(), seedhash);
+                light
+            }));
+            if !light_mode {
+                full = cached("dag", full_size, &|| {
+                    let bytes = Arc::from(FullBytes::new(full_size));
+                    let progress = Progress::new(format!("generating the dag of epoch {}", epoch), full_size / HASH_BYTES);
+                    make_full(&bytes, &light, &progress);
+                    bytes
+                });
+            }
             warn!("Computer::new ok, epoch-seedhash: {}-{}", epoch, seedhash);
         } else {
             error!("Computer::new skip, wokrers: {}, epoch-seedhash: {}-{}", wokrers, epoch, seedhash);
         };
 
-        Self { epoch, full }
+        Self {
+            epoch,
+            full_size,
+            full: Arc::new(full),
+            light,
+        }
     }
     pub fn epoch(&self) -> usize {
         self.epoch
     }
+    // the memory of the dag and the cache
     pub fn size(&self) -> usize {
-        self.full.size()
+        self.full.as_bytes().len() + self.light.as_bytes().len()
     }
     pub fn compute(&self, job: &Job, nonce: &U64) -> Option<Solution> {
         let mut solution = self.compute_raw(job, nonce);
@@ -151,7 +193,11 @@ impl Computer {
         let full = self.full.as_bytes();
         let nonce = H64::from_uint(nonce);
 
-        let (mixed_hash, result) = ethash::hashimoto_full(job.powhash, nonce, full.len(), full);
+        let (mixed_hash, result) = if full.is_empty() {
+            ethash::hashimoto_light(job.powhash, nonce, self.full_size, self.light.as_bytes())
+        } else {
+            ethash::hashimoto_full(job.powhash, nonce, full.len(), full)
+        };
 
         Solution {
             id: job.id,
@@ -160,14 +206,76 @@ impl Computer {
             nonce,
         }
     }
+    // recomputes the solution by the cache, a broken dag never gets submitted
+    pub fn verify(&self, job: &Job, solution: &Solution) -> bool {
+        let (mixed_hash, result) = ethash::hashimoto_light(job.powhash, solution.nonce, self.full_size, self.light.as_bytes());
+        mixed_hash == solution.mixed_hash && result == solution.target && result <= job.target
+    }
+}
+
+// loads the dataset from the path, or makes it then switches to the mmap of the saved
+fn load_or_make(path: Option<&Path>, size: usize, make: &dyn Fn() -> Arc<FullBytes>) -> Dataset {
+    match path.map(|p| dag::load(p, size)) {
+        Some(Ok(f)) => {
+            warn!("Computer::new loaded {}", path.unwrap().display());
+            return Dataset::File(f);
+        }
+        Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => warn!("ignore the dag {}: {}", path.unwrap().display(), e),
+        _ => {}
+    }
+
+    let dataset = Dataset::Bytes(make());
+    if let Some(p) = path {
+        // the memory of the generated is freed after switching
+        match dag::save(p, dataset.as_bytes()).and_then(|_| dag::load(p, size)) {
+            Ok(f) => {
+                warn!("Computer::new saved {}", p.display());
+                return Dataset::File(f);
+            }
+            Err(e) => warn!("save the dag {} failed: {}", p.display(), e),
+        }
+    }
+    dataset
 }
 
 use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const HASH_BYTES: usize = 64;
+
+// logs the percent and the eta every 5%
+pub struct Progress {
+    name: String,
+    total: usize,
+    done: AtomicUsize,
+    start: Instant,
+}
+
+impl Progress {
+    pub fn new(name: String, total: usize) -> Self {
+        Self {
+            name,
+            total,
+            done: AtomicUsize::new(0),
+            start: Instant::now(),
+        }
+    }
+    pub fn add(&self, n: usize) {
+        let done = self.done.fetch_add(n, Ordering::Relaxed) + n;
+        let step = (self.total / 20).max(1);
+        if (done - n) / step != done / step || done >= self.total {
+            let elapsed = self.start.elapsed();
+            let eta = Duration::from_secs_f64(elapsed.as_secs_f64() * self.total.saturating_sub(done) as f64 / done as f64);
+            warn!("{}: {}%, costed {:?}, eta {:?}", self.name, done * 100 / self.total, elapsed, eta);
+        }
+    }
+}
 
 /// a parallel implementation for https://docs.rs/ethash/0.3.1/src/ethash/lib.rs.html#176-184
 /// it will take more than 40 minutes if using single thread only
-pub fn make_full(full: &Arc<FullBytes>, cache: &Arc<Vec<u8>>) {
-    const HASH_BYTES: usize = 64;
+pub fn make_full(full: &Arc<FullBytes>, cache: &Arc<Dataset>, progress: &Progress) {
+    const PROGRESS_ITEMS: usize = 4096;
 
     let dataset = full.as_bytes();
     let n_scope = dataset.len() / HASH_BYTES;
@@ -178,11 +286,14 @@ pub fn make_full(full: &Arc<FullBytes>, cache: &Arc<Vec<u8>>) {
 
     tasks.into_par_iter().for_each(move |(tasks, full, cache)| {
         let dataset = full.as_mut_bytes();
-        for i in tasks {
-            let z = ethash::calc_dataset_item(&cache, *i);
+        for (idx, i) in tasks.iter().enumerate() {
+            let z = ethash::calc_dataset_item(cache.as_bytes(), *i);
             for j in 0..64 {
                 dataset[i * 64 + j] = z[j];
             }
+            if (idx + 1) % PROGRESS_ITEMS == 0 || idx + 1 == tasks.len() {
+                progress.add((idx % PROGRESS_ITEMS) + 1);
+            }
         }
     })
 }
@@ -11,7 +11,8 @@ pub struct FormJob {
 }
 
 impl FormJob {
-    pub fn to_job(&self) -> Result<Job, &'static str> {
+    // the epoch of job is the seed epoch of the chain
+    pub fn to_job(&self, chain: Chain) -> Result<Job, &'static str> {
         if self.result.len() < 3 {
             return Err("invalid job params");
         }
@@ -41,11 +42,14 @@ impl FormJob {
             let target = U256::from(base) << (256 - 24) >> exp;
 
             let height = usize::from_str_radix(&self.result[2], 16).map_err(|_| "decode height error")?;
-            let epoch = height / 30000;
+            let epoch = chain.seed_epoch(height);
 
             return Ok(Job {
                 powhash,
                 epoch,
+                height: Some(height),
+                jobid: None,
+                extranonce: Extranonce::default(),
                 id: 0,
                 seedhash: None,
                 target: H256::from_uint(&target),
@@ -59,30 +63,29 @@ impl FormJob {
             target = "0".repeat(64 - target.len()) + &target;
         }
 
+        // the 4th is the block number of geth, and most pools
+        let height = self.result.get(3).and_then(|h| usize::from_str_radix(clean_0x(h), 16).ok());
+
         Ok(Job {
             powhash: clean_0x(&self.result[0]).parse().map_err(|_| "decode powhash error")?,
             seedhash: Some(clean_0x(&self.result[1]).parse().map_err(|_| "decode seedhash error")?),
             target: target.parse().map_err(|_| "decode target error")?,
             epoch: get_epoch_number(&seedhash).map_err(|()| "get epoch error")?,
+            height,
             nonce: rand::random::<u64>().into(),
+            jobid: None,
+            extranonce: Extranonce::default(),
             id: 0,
         })
     }
 }
 
-// {"id":1,"jsonrpc":"2.0","result":true}
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct FormResult {
-    pub id: usize,
-    pub result: bool,
-    pub error: Option<Value>,
-}
-
-use crate::config::Config;
+use crate::chain::Chain;
+use crate::config::{EthDialect, Pool, LOGIN_ETH, LOGIN_STRATUM};
 use crate::eth::pow::get_epoch_number;
 use crate::state::Req;
-use crate::util::clean_0x;
+use crate::stratum::{Notify, Request, Response, METHOD_SET_EXTRANONCE};
+use crate::util::{atomic_id, clean_0x, difficulty_to_target};
 use bigint::{H256, H64, U64};
 
 #[derive(Debug, Clone, Hash)]
@@ -91,8 +94,45 @@ pub struct Job {
     pub powhash: H256,
     pub target: H256,
     pub epoch: usize,
+    pub height: Option<usize>,
     pub nonce: U64,
     pub seedhash: Option<H256>,
+    // the job of EthereumStratum/1.0.0, its nonce starts with the extranonce
+    pub jobid: Option<String>,
+    pub extranonce: Extranonce,
+}
+
+// the extranonce of EthereumStratum/1.0.0 takes the high bytes of the nonce
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Extranonce {
+    pub prefix: u64,
+    pub bytes: usize,
+}
+
+impl Extranonce {
+    pub fn parse(hex: &str) -> Result<Self, &'static str> {
+        let bytes = hex.len() / 2;
+        if hex.len() % 2 == 1 || bytes > 6 {
+            return Err("invalid extranonce");
+        }
+        let mut nbs = [0u8; 8];
+        faster_hex::hex_decode(hex.as_bytes(), &mut nbs[..bytes]).map_err(|_| "invalid extranonce hex")?;
+        Ok(Self {
+            prefix: u64::from_be_bytes(nbs),
+            bytes,
+        })
+    }
+    fn mask(&self) -> u64 {
+        u64::MAX >> (self.bytes * 8)
+    }
+    // a random start leaves the half of the space for the workers
+    pub fn start_nonce(&self) -> u64 {
+        self.prefix | (rand::random::<u64>() & (self.mask() >> 1))
+    }
+    // the nonce without the extranonce
+    pub fn suffix(&self, nonce: u64) -> String {
+        format!("{:01$x}", nonce & self.mask(), 16 - self.bytes * 2)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -108,13 +148,25 @@ pub const METHOD_GET_WORK: &str = "eth_getWork";
 pub const METHOD_SUBMIT_WORK: &str = "eth_submitWork";
 pub const METHOD_SUBMIT_HASHRATE: &str = "eth_submitHashrate";
 
+pub const METHOD_SUBSCRIBE: &str = "mining.subscribe";
+pub const METHOD_AUTHORIZE: &str = "mining.authorize";
+pub const METHOD_SET_DIFFICULTY: &str = "mining.set_difficulty";
+pub const METHOD_NOTIFY: &str = "mining.notify";
+pub const METHOD_SUBMIT: &str = "mining.submit";
+pub const ETHEREUM_STRATUM: &str = "EthereumStratum/1.0.0";
+
 // {"id":5,"method":"eth_submitWork","params":["0x43d4146cf7fe1d4e","0x2e4635265502a0f070d2d16a424f55aa797b915406de5e3685822c8d71d42e86","0x7e830f66cbd3e388920c71b92bf4d1cf429d7581854a3926841314a28530b54a"],"worker":"xox"}
-pub fn make_submit(solution: &Solution, job: &Job) -> Option<Req> {
-    let req = format!(
-        r#"{{"id":{},"method":"{}","params":["{:?}", "{:?}", "{:?}"]}}"#,
-        solution.id, METHOD_SUBMIT_WORK, solution.nonce, job.powhash, solution.mixed_hash
-    );
-    Some((solution.id, METHOD_SUBMIT_WORK, req).into())
+// {"id":4,"method":"mining.submit","params":["sp_yos.0v0","bf0488aa","6a909d9bbc0f"]}
+pub fn make_submit(solution: &Solution, job: &Job, pool: &Pool) -> Option<Req> {
+    if let Some(jobid) = job.jobid.as_ref() {
+        let nonce = solution.nonce.to_low_u64_be();
+        let worker = pool.login_params(LOGIN_STRATUM).into_iter().next().unwrap_or_default();
+        return Some(Request::new(solution.id, METHOD_SUBMIT, (worker, jobid, job.extranonce.suffix(nonce))).into_req());
+    }
+
+    let params = [format!("{:?}", solution.nonce), format!("{:?}", job.powhash), format!("{:?}", solution.mixed_hash)];
+    let req = Request::new(solution.id, METHOD_SUBMIT_WORK, params);
+    Some(if pool.addr.scheme.is_http() { req.jsonrpc() } else { req }.into_req())
 }
 
 #[test]
@@ -137,11 +189,23 @@ fn test_nonce_format() {
 
 // '{"jsonrpc":"2.0", "method":"eth_submitHashrate", "params":["0xc76cc9", "0x59daa26581d0acd1fce254fb7e85952f4c09d0915afd33d3886cd914bc7d283c"],"id":73}'
 pub fn make_hashrate(hashrate: u64) -> Req {
-    let req = format!(
-        r#"{{"jsonrpc":"2.0", "method":"eth_submitHashrate", "params":["{:#0x}", "0x0000000000000000000000000000000000000000000000000000000000000000"],"id":1}}"#,
-        hashrate
-    );
-    (1, METHOD_SUBMIT_HASHRATE, req).into()
+    let params = [format!("{:#0x}", hashrate), format!("{:#066x}", 0)];
+    Request::new(1, METHOD_SUBMIT_HASHRATE, params).jsonrpc().into_req()
+}
+
+#[test]
+fn test_ethereum_stratum() {
+    let extranonce = Extranonce::parse("080c").unwrap();
+    assert_eq!(extranonce.prefix, 0x080c_0000_0000_0000);
+    let nonce = extranonce.start_nonce();
+    assert_eq!(nonce >> 48, 0x080c);
+    assert_eq!(extranonce.suffix(0x080c_6a90_9d9b_bc0f), "6a909d9bbc0f");
+    assert_eq!(Extranonce::default().suffix(0x6a90), "0000000000006a90");
+    assert!(Extranonce::parse("080").is_err());
+    assert!(Extranonce::parse("00112233445566").is_err());
+
+    assert_eq!(stratum_difficulty_to_target(1.0), H256::from_uint(&(U256::one() << 224)));
+    assert_eq!(stratum_difficulty_to_target(0.5), H256::from_uint(&(U256::one() << 225)));
 }
 
 #[test]
@@ -157,13 +221,97 @@ fn test_hashrate_generate() {
     }
 }
 
-// {"id":1,"method":"eth_submitLogin","params":["sp_yos.0v0"],"worker":"0v0"}
+// r: {"id":1,"method":"mining.subscribe","params":["cminer/0.3.1","EthereumStratum/1.0.0"]}
+// p: {"id":1,"result":[["mining.notify","ae6812eb4cd7735a302a8a9dd95cf71f","EthereumStratum/1.0.0"],"080c"],"error":null}
+pub fn make_subscribe() -> Req {
+    let agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    Request::new(atomic_id(), METHOD_SUBSCRIBE, (agent, ETHEREUM_STRATUM)).into_req()
+}
+
+// r: {"id":2,"method":"mining.authorize","params":["sp_yos.0v0","x"]}
+// p: {"id":2,"result":true,"error":null}
+pub fn make_authorize(pool: &Pool) -> Req {
+    Request::new(atomic_id(), METHOD_AUTHORIZE, pool.login_params(LOGIN_STRATUM)).into_req()
+}
+
+// {"id":null,"method":"mining.notify","params":["bf0488aa","abad8f99f3918bf903c6a909d9bbc0fdfa5a2f4b9cb1196175ec825c6610126c","645cf20198c2f3861e947d4f67e3ab63b7b2e24dcc9095bd9123e7b33371f6cc",true]}
+// (jobid, seedhash, headerhash, clean)
+pub type FormNotify = (String, String, String, bool);
+// {"id":null,"method":"mining.set_difficulty","params":[0.5]}
+pub type FormSetDifficulty = (f64,);
+
+#[derive(Debug, Clone)]
+pub enum MethodParams {
+    Job(Job),
+    Target(H256),
+    Extranonce(Extranonce),
+}
+
+// the difficulty 1 of EthereumStratum/1.0.0 is 2^32 hashes
+pub fn stratum_difficulty_to_target(diff: f64) -> H256 {
+    difficulty_to_target(&U256::from((diff * 4294967296.0) as u128))
+}
+
+// the target and the extranonce of the job are set by the state
+pub fn parse_notify(notify: Notify) -> Result<MethodParams, &'static str> {
+    match notify.method.as_str() {
+        METHOD_NOTIFY => {
+            let (jobid, seedhash, powhash, _clean): FormNotify = notify.params().map_err(|_| "invalid mining.notify params")?;
+            let seedhash = clean_0x(&seedhash).parse().map_err(|_| "decode seedhash error")?;
+            Ok(MethodParams::Job(Job {
+                id: 0,
+                powhash: clean_0x(&powhash).parse().map_err(|_| "decode powhash error")?,
+                target: H256::zero(),
+                epoch: get_epoch_number(&seedhash).map_err(|()| "get epoch error")?,
+                height: None,
+                nonce: 0.into(),
+                seedhash: Some(seedhash),
+                jobid: Some(jobid),
+                extranonce: Extranonce::default(),
+            }))
+        }
+        METHOD_SET_DIFFICULTY => {
+            let (diff,): FormSetDifficulty = notify.params().map_err(|_| "invalid mining.set_difficulty params")?;
+            info!("{}: {}", METHOD_SET_DIFFICULTY, diff);
+            Ok(MethodParams::Target(stratum_difficulty_to_target(diff)))
+        }
+        METHOD_SET_EXTRANONCE => {
+            let hex = notify.params.as_array().and_then(|a| a.first()).and_then(|s| s.as_str()).ok_or("malform set_extranonce")?;
+            info!("{}: {}", METHOD_SET_EXTRANONCE, hex);
+            Extranonce::parse(hex).map(MethodParams::Extranonce)
+        }
+        _ => Err("unsupported notify"),
+    }
+}
+
+// the extranonce of the result of mining.subscribe
+pub fn parse_subscribe(resp: &Response) -> Result<Extranonce, &'static str> {
+    let (_, extranonce): (Value, String) = resp.result().map_err(|_| "invalid mining.subscribe result")?;
+    Extranonce::parse(&extranonce)
+}
+
+// the auto dialect subscribes first, then authorizes or falls back to eth-proxy by the result
+pub fn make_login(pool: &Pool, dialect: EthDialect) -> Vec<Req> {
+    match dialect {
+        EthDialect::Proxy => make_proxy_login(pool),
+        EthDialect::Stratum => vec![make_subscribe(), make_authorize(pool)],
+        EthDialect::Auto => vec![make_subscribe()],
+    }
+}
+
+// {"id":1,"method":"eth_submitLogin","params":["sp_yos.0v0"],"worker":"0v0","compact":true}
 // {"id":2,"method":"eth_getWork","params":[]}
-pub fn make_login(config: &Config) -> Req {
-    let login = format!(
-        r#"{{"id":1,"method":"{}","params":["{}.{}"],"worker":"{}","compact":true}}
-{{"id":1,"method":"{}","params":[]}}"#,
-        METHOD_LOGIN, config.user, config.rig, config.rig, METHOD_GET_WORK
-    );
-    (1, METHOD_LOGIN, login).into()
+// only the params are by the login template, the worker is always the rig and compact is always true
+pub fn make_proxy_login(pool: &Pool) -> Vec<Req> {
+    let (login_id, getwork_id) = (atomic_id(), atomic_id());
+    vec![
+        Request::new(login_id, METHOD_LOGIN, pool.login_params(LOGIN_ETH)).worker(&pool.rig).compact(true).into_req(),
+        Request::new(getwork_id, METHOD_GET_WORK, [(); 0]).into_req(),
+    ]
+}
+
+// polled from the node of http(s):// pool, such as geth
+// {"jsonrpc":"2.0","id":3,"method":"eth_getWork","params":[]}
+pub fn make_getwork() -> Req {
+    Request::new(atomic_id(), METHOD_GET_WORK, [(); 0]).jsonrpc().into_req()
 }
@@ -1,16 +1,19 @@
 use kaspow::{target2difficulty, Uint256};
-use serde_json;
 use std::mem;
 
 pub mod pow;
 pub mod proto;
 
-use crate::config::TIMEOUT_SECS;
+use crate::config::{Config, TIMEOUT_SECS};
 use crate::state::{Handle, Handler, Job as JobID, Req, Run, State, Worker};
-use crate::util;
+use crate::stratum::{self, Message, Response};
+use crate::util::{self, LoginError};
 
 use pow::Computer;
-use proto::{make_hashrate, make_login, make_submit, Job, MethodForm, MethodParams, ResultForm, METHOD_SUBMIT_WORK};
+use proto::{
+    make_hashrate, make_login, make_submit, make_template, parse_notify, parse_response, parse_rpc, parse_submit_report, parse_template, FormTemplate, Job, MethodParams, METHOD_AUTHORIZE,
+    METHOD_GET_TEMPLATE, METHOD_SUBMIT_BLOCK, METHOD_SUBMIT_WORK,
+};
 
 #[derive(Debug, Clone)]
 pub enum KasJob {
@@ -22,6 +25,8 @@ pub enum KasJob {
 }
 
 impl JobID for KasJob {
+    type Shared = ();
+    fn shared(_: &Config) -> Self::Shared {}
     fn jobid(&self) -> String {
         match &self {
             Self::Compute(job) => job.jobid.clone(),
@@ -36,17 +41,98 @@ impl Default for KasJob {
     }
 }
 
+impl State<KasJob> {
+    fn payout(&self) -> &str {
+        self.config().payout.as_deref().expect("payout checked by config")
+    }
+    // the job of getBlockTemplate, the same header with only a newer timestamp is skipped
+    fn handle_template(&self, rf: &Response) -> util::Result<()> {
+        let form = rf.result::<FormTemplate>();
+        let mut lock = self.value().lock();
+        let lock = &mut *lock;
+        let req = lock.reqs.remove(rf.id);
+        let form = match form {
+            Ok(form) => form,
+            Err(e) => {
+                error!("{} failed: {}, error: {:?}", METHOD_GET_TEMPLATE, e, rf.error_string());
+                return Ok(());
+            }
+        };
+        if !form.is_synced {
+            warn!("kaspad isn't synced, wait for it");
+            return Ok(());
+        }
+
+        let mut j = parse_template(&form)?;
+        match &lock.job {
+            KasJob::Compute(oj) if oj.powhash == j.powhash => return Ok(()),
+            KasJob::Exit => return Ok(()),
+            _ => {}
+        }
+
+        j.id = lock.jobsc.get() + 1;
+        j.nonce = rand::random::<u64>() / 2;
+        info!(
+            "template: {}, diff: {}, prepowhash: {}, timestamp: {}, nonce: {:016x}, costed: {:?}",
+            j.jobid,
+            target2difficulty(&j.target),
+            j.powhash,
+            j.timestamp,
+            j.nonce,
+            req.map(|r| r.time.elapsed())
+        );
+        lock.job = KasJob::Compute(j);
+        lock.jobsc.add_slow(1);
+        Ok(())
+    }
+    fn handle_submit_block(&self, rf: &Response) {
+        let mut lock = self.value().lock();
+        let lock = &mut *lock;
+        let costed = lock.reqs.remove(rf.id).map(|r| r.time.elapsed());
+        if parse_submit_report(rf) {
+            lock.acceptc += 1;
+            warn!("block {} accepted {:?}", rf.id, costed);
+        } else {
+            lock.rejectc += 1;
+            error!("block {} rejected {:?}, result: {}, error: {:?}", rf.id, costed, rf.result, rf.error_string());
+        }
+    }
+    // the responses of kaspad by the wrpc(s):// pool
+    fn handle_rpc(&self, resp: &str) -> util::Result<()> {
+        let rf = parse_rpc(resp)?;
+        let method = self.value().lock().reqs.get(rf.id).map(|r| r.method);
+        match method {
+            Some(METHOD_GET_TEMPLATE) => self.handle_template(&rf)?,
+            Some(METHOD_SUBMIT_BLOCK) => self.handle_submit_block(&rf),
+            _ => warn!("unkown response id: {}, result: {}, error: {:?}", rf.id, rf.result, rf.error_string()),
+        }
+        Ok(())
+    }
+}
+
 impl Handle for State<KasJob> {
-    fn login_request(&self) -> Req {
-        make_login(&self.config())
+    // kaspad of wrpc(s):// pool has no login
+    fn login_request(&self) -> Vec<Req> {
+        if self.pool().addr.scheme.is_wrpc() {
+            return vec![make_template(self.payout())];
+        }
+        make_login(self.config(), self.pool())
     }
     fn hashrate_request(&self, hashrate: u64) -> Option<Req> {
-        Some(make_hashrate(hashrate))
+        Some(make_hashrate(hashrate)).filter(|_| !self.pool().addr.scheme.is_wrpc())
+    }
+    fn poll_request(&self) -> Option<Req> {
+        if self.pool().addr.scheme.is_wrpc() {
+            return Some(make_template(self.payout()));
+        }
+        None
     }
     fn handle_request(&self, req: Req) -> util::Result<String> {
         let mut lock = self.value().lock();
-        lock.reqs.add(&req);
-        if req.1 == METHOD_SUBMIT_WORK {
+        if req.1 != stratum::METHOD_REPLY {
+            lock.reqs.add(&req);
+        }
+        if req.1 == METHOD_SUBMIT_WORK || req.1 == METHOD_SUBMIT_BLOCK {
             (*lock).submitc += 1;
         }
         trace!("id: {}, method: {}, req: {}", req.0, req.1, req.2);
@@ -54,9 +140,17 @@ impl Handle for State<KasJob> {
     }
     fn handle_response(&self, resp: String) -> util::Result<()> {
         trace!("resp: {}", resp);
+        if self.pool().addr.scheme.is_wrpc() {
+            return self.handle_rpc(&resp);
+        }
 
-        if let Ok(jf) = serde_json::from_str::<MethodForm>(&resp) {
-            match jf.to_params() {
+        let msg = stratum::parse(&resp);
+        if let Ok(Message::Notify(notify)) = msg {
+            let notify = match stratum::handle_notify(self, notify)? {
+                Some(notify) => notify,
+                None => return Ok(()),
+            };
+            match parse_notify(notify) {
                 Ok(jt) => {
                     let mut lock = self.value().lock();
                     let lock = &mut *lock;
@@ -104,11 +198,12 @@ impl Handle for State<KasJob> {
                         }
                         MethodParams::Nonce1t((n1, n1b)) => {
                             let job = match mem::replace(&mut lock.job, KasJob::Sleep) {
-                                KasJob::Sleep => KasJob::Nonce1t((0, 0, 0.into())),
+                                KasJob::Sleep => KasJob::Nonce1t((n1, n1b, 0.into())),
                                 KasJob::Nonce1t((_n1, _n1b, t)) => KasJob::Nonce1t((n1, n1b, t)),
                                 KasJob::Compute(mut job) => {
                                     job.nonce = n1;
                                     job.nonce1_bytes = n1b;
+                                    lock.jobsc.add_slow(1);
                                     KasJob::Compute(job)
                                 }
                                 other => other,
@@ -119,9 +214,9 @@ impl Handle for State<KasJob> {
                 }
                 Err(e) => error!("handle job({}) error: {}", resp, e),
             }
-        } else if let Ok(rf) = serde_json::from_str::<ResultForm>(&resp) {
+        } else if let Ok(Message::Response(rf)) = msg {
             // <(id, bool, _)
-            match rf.to_result() {
+            match parse_response(&rf) {
                 Ok((id, b, e)) => {
                     let mut lock = self.value().lock();
                     let lock = &mut *lock;
@@ -136,6 +231,8 @@ impl Handle for State<KasJob> {
                                 lock.rejectc += 1;
                                 error!("submit {} rejected {:?}, error: {:?}", req.id, costed, e);
                             }
+                        } else if req.method == METHOD_AUTHORIZE && !b {
+                            return Err(LoginError::new(req.method, self.pool(), e).into());
                         } else {
                             info!("request {}#{} {:?}, error: {:?}", req.id, req.method, costed, e);
                         }
@@ -1,19 +1,13 @@
 use faster_hex::hex_string;
-use kaspow::{difficulty_decompress, matrix::Matrix, target2difficulty, Hash, PowHash, Uint256};
-use serde_json::Value;
+use kaspow::header::{hash_override_nonce_time, Header};
+use kaspow::{difficulty_decompress, matrix::Matrix, target2difficulty, Hash, PowHash, State as PowState, Uint256};
+use serde_json::{json, Value};
 use std::sync::Arc;
 
-use crate::config::Config;
+use crate::config::{Config, Pool, LOGIN_STRATUM};
 use crate::state::Req;
-
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct MethodForm {
-    pub id: Option<Value>,
-    pub method: String,
-    pub params: Value,
-    pub error: Option<Value>,
-}
+use crate::stratum::{Notify, Request, Response, METHOD_SET_EXTRANONCE};
+use crate::util::{atomic_id, Result as UResult};
 
 // {"id":null,"jsonrpc":"2.0","method":"mining.set_difficulty","params":[2.3283064365386963]}
 pub type FormSetDifficulty = (f64,);
@@ -78,6 +72,7 @@ pub fn parse_job(js: Value) -> Result<Job, &'static str> {
         target: Default::default(),
         nonce: 0,
         id: 0,
+        block: None,
     })
 }
 
@@ -95,30 +90,29 @@ pub fn parse_nonce(nonce1: &str) -> (u64, usize) {
     (nonce, nonce1_bytes)
 }
 
-impl MethodForm {
-    pub fn to_params(self) -> Result<MethodParams, &'static str> {
-        let method = self.method.as_str();
-        if method == METHOD_NOTIFY {
-            parse_job(self.params).map(MethodParams::Job)
-        } else if method == METHOD_SET_TARGET {
-            serde_json::from_value(self.params).map_err(|_| "deser_difficulty error").and_then(|p: FormSetDifficulty| {
-                let diff = difficulty_decompress(p.0);
-                let target = target2difficulty(&diff.into());
-
-                info!("{} {}: {}", method, p.0, diff);
-                Ok(MethodParams::Target(target))
-            })
-        } else if [METHOD_SET_EXTRANONCE, "set_extranonce"].contains(&method) {
-            let hex = self.params.as_array().and_then(|a| a.get(0)).and_then(|s| s.as_str());
-            if hex.is_none() {
-                return Err("malform set_extranonce");
-            }
-            let info = parse_nonce(hex.unwrap());
-            info!("{} {}: {} {}bytes", method, hex.unwrap(), info.0, info.1);
-            Ok(MethodParams::Nonce1t(info))
-        } else {
-            Err("unkown MethodForm")
+pub fn parse_notify(notify: Notify) -> Result<MethodParams, &'static str> {
+    let method = notify.method.clone();
+    let method = method.as_str();
+    if method == METHOD_NOTIFY {
+        parse_job(notify.params).map(MethodParams::Job)
+    } else if method == METHOD_SET_TARGET {
+        notify.params().map_err(|_| "deser_difficulty error").map(|p: FormSetDifficulty| {
+            let diff = difficulty_decompress(p.0);
+            let target = target2difficulty(&diff.into());
+
+            info!("{} {}: {}", method, p.0, diff);
+            MethodParams::Target(target)
+        })
+    } else if [METHOD_SET_EXTRANONCE, "set_extranonce"].contains(&method) {
+        let hex = notify.params.as_array().and_then(|a| a.first()).and_then(|s| s.as_str());
+        if hex.is_none() {
+            return Err("malform set_extranonce");
         }
+        let info = parse_nonce(hex.unwrap());
+        info!("{} {}: {} {}bytes", method, hex.unwrap(), info.0, info.1);
+        Ok(MethodParams::Nonce1t(info))
+    } else {
+        Err("unkown notify")
     }
 }
 
@@ -139,6 +133,8 @@ pub struct Job {
     pub nonce: u64,
     pub nonce1_bytes: usize,
     pub matrixhasher: Arc<(Matrix, PowHash)>,
+    // the block of the template from kaspad, submitted with the nonce
+    pub block: Option<Arc<Value>>,
 }
 
 use std::fmt;
@@ -166,7 +162,6 @@ pub struct Solution {
 pub const METHOD_SUBSCRIBE: &str = "mining.subscribe";
 pub const METHOD_AUTHORIZE: &str = "mining.authorize";
 pub const METHOD_SET_TARGET: &str = "mining.set_difficulty";
-pub const METHOD_SET_EXTRANONCE: &str = "mining.set_extranonce";
 pub const METHOD_SUBMIT_HASHRATE: &str = "mining.submit_hashrate";
 pub const METHOD_NOTIFY: &str = "mining.notify";
 pub const METHOD_SUBMIT_WORK: &str = "mining.submit";
@@ -174,20 +169,20 @@ pub const METHOD_SUBMIT_WORK: &str = "mining.submit";
 // {"id":8,"method":"mining.submit","params":["sp_test.worker1","b1404ef2","000000000000000000084148"]}
 // {"id":8,"result":true,"error":null}
 pub fn make_submit(solution: &Solution, job: &Job) -> Option<Req> {
+    if let Some(block) = job.block.as_ref() {
+        return Some(make_submit_block(solution, block));
+    }
+
     let nonce_bytes = solution.nonce.to_be_bytes();
     let nonce_submit = hex_string(&nonce_bytes);
 
-    let req = format!(r#"{{"id":{},"method":"{}","params":["{}","{}","{}"]}}"#, solution.id, METHOD_SUBMIT_WORK, "", job.jobid, nonce_submit);
-    Some((solution.id, METHOD_SUBMIT_WORK, req).into())
+    Some(Request::new(solution.id, METHOD_SUBMIT_WORK, ("", &job.jobid, nonce_submit)).into_req())
 }
 
 // {"id":9,"method":"mining.submit_hashrate","jsonrpc":"2.0","worker":"456-027","params":["0x000000000000000000000000ab5d1ce0","0xf3369d5a95fb31e9217f03484be600135c6c8250341ac4e7212269292e3ceb84"]}
 pub fn make_hashrate(hashrate: u64) -> Req {
-    let req = format!(
-        r#"{{"jsonrpc":"2.0", "method":"{}", "params":["{:#0x}", "0x0000000000000000000000000000000000000000000000000000000000000000"],"id":1}}"#,
-        METHOD_SUBMIT_HASHRATE, hashrate
-    );
-    (1, METHOD_SUBMIT_HASHRATE, req).into()
+    let params = [format!("{:#0x}", hashrate), format!("{:#066x}", 0)];
+    Request::new(1, METHOD_SUBMIT_HASHRATE, params).jsonrpc().into_req()
 }
 
 // r: {"id":1,"method":"mining.subscribe","params":["BzMiner/v12.1.1","EthereumStratum/1.0.0"]}
@@ -195,46 +190,183 @@ pub fn make_hashrate(hashrate: u64) -> Req {
 
 // r: {"id":0,"method":"mining.authorize","params":["sp_test.worker1","x"]}
 // p: {"id":0,"result":true,"error":null}
-pub fn make_login(config: &Config) -> Req {
+pub fn make_login(config: &Config, pool: &Pool) -> Vec<Req> {
     let mut notify_hex = "";
-    if config.testnet {
+    if config.chain().is_testnet() {
         notify_hex = ".BzMinerLike";
     }
 
-    let login = format!(
-        r#"{{"id":0,"method":"{}","params":["{}{}/{}","EthereumStratum/1.0.0"]}}
-{{"id":1,"method":"{}","params":["{}.{}","x"]}}"#,
-        METHOD_SUBSCRIBE,
-        env!("CARGO_PKG_NAME"),
-        notify_hex,
-        env!("CARGO_PKG_VERSION"),
-        METHOD_AUTHORIZE,
-        config.user,
-        config.rig
-    );
-    (0, METHOD_SUBSCRIBE, login).into()
-}
-
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ResultForm {
-    pub id: usize,
-    pub result: Value,
-    pub error: Option<Value>,
+    let (subscribe_id, authorize_id) = (atomic_id(), atomic_id());
+    let agent = format!("{}{}/{}", env!("CARGO_PKG_NAME"), notify_hex, env!("CARGO_PKG_VERSION"));
+    vec![
+        Request::new(subscribe_id, METHOD_SUBSCRIBE, (agent, "EthereumStratum/1.0.0")).into_req(),
+        Request::new(authorize_id, METHOD_AUTHORIZE, pool.login_params(LOGIN_STRATUM)).into_req(),
+    ]
 }
 
-impl ResultForm {
-    // <(id, bool, _)
-    pub fn to_result(&self) -> Result<(usize, bool, Option<String>), &'static str> {
-        if let Ok((b, s)) = serde_json::from_value::<(bool, String)>(self.result.clone()) {
-            info!("{} {}: {}", METHOD_SUBSCRIBE, b, s);
-            return Ok((self.id, b, self.error.as_ref().map(|e| format!("{:?}", e))));
-        }
+// <(id, bool, _)
+pub fn parse_response(resp: &Response) -> Result<(usize, bool, Option<String>), &'static str> {
+    if let Ok((b, s)) = resp.result::<(bool, String)>() {
+        info!("{} {}: {}", METHOD_SUBSCRIBE, b, s);
+        return Ok((resp.id, b, resp.error_string()));
+    }
 
-        if let Ok(b) = serde_json::from_value::<bool>(self.result.clone()) {
-            return Ok((self.id, b, self.error.as_ref().map(|e| format!("{:?}", e))));
-        }
+    resp.to_bool().map(|b| (resp.id, b, resp.error_string())).ok_or("invalid response")
+}
+
+pub const METHOD_GET_TEMPLATE: &str = "getBlockTemplate";
+pub const METHOD_SUBMIT_BLOCK: &str = "submitBlock";
+
+// the json wrpc of kaspad carries the result in params
+// {"id":1,"params":{"block":{"header":{..},"transactions":[..]},"isSynced":true}}
+// {"id":1,"error":{"message":"..."}}
+pub fn parse_rpc(resp: &str) -> UResult<Response> {
+    let mut raw: Value = serde_json::from_str(resp)?;
+    let id = raw["id"].as_u64().ok_or_else(|| format_err!("the wrpc message without id"))?;
+    let result = match raw["params"].take() {
+        Value::Null => raw["result"].take(),
+        params => params,
+    };
+    Ok(Response {
+        id: id as usize,
+        result,
+        error: Some(raw["error"].take()).filter(|e| !e.is_null()),
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormTemplate {
+    pub block: Value,
+    pub is_synced: bool,
+}
+
+// the hashes are hex in the bytes order, and the blue work is the big-endian hex of Uint192
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormHeader {
+    pub version: u16,
+    pub parents_by_level: Vec<Vec<String>>,
+    pub hash_merkle_root: String,
+    pub accepted_id_merkle_root: String,
+    pub utxo_commitment: String,
+    pub timestamp: u64,
+    pub bits: u32,
+    pub daa_score: u64,
+    pub blue_work: String,
+    pub blue_score: u64,
+    pub pruning_point: String,
+}
 
-        Err("Invalid ResultForm")
+impl FormHeader {
+    pub fn to_header(&self) -> UResult<Header> {
+        let hash = |h: &String| h.parse::<Hash>().map_err(|e| format_err!("invalid hash {} of header: {}", h, e));
+        let blue_work = self.blue_work.trim_start_matches("0x");
+        let blue_work = if blue_work.len() % 2 == 1 { format!("0{}", blue_work) } else { blue_work.to_owned() };
+
+        Ok(Header {
+            version: self.version,
+            parents_by_level: self.parents_by_level.iter().map(|l| l.iter().map(hash).collect()).collect::<UResult<_>>()?,
+            hash_merkle_root: hash(&self.hash_merkle_root)?,
+            accepted_id_merkle_root: hash(&self.accepted_id_merkle_root)?,
+            utxo_commitment: hash(&self.utxo_commitment)?,
+            timestamp: self.timestamp,
+            bits: self.bits,
+            nonce: 0,
+            daa_score: self.daa_score,
+            blue_work: hex::decode(blue_work)?,
+            blue_score: self.blue_score,
+            pruning_point: hash(&self.pruning_point)?,
+        })
     }
 }
+
+// {"id":3,"method":"getBlockTemplate","params":{"payAddress":"kaspa:qr...","extraData":""}}
+pub fn make_template(payout: &str) -> Req {
+    let params = json!({ "payAddress": payout, "extraData": "" });
+    Request::new(atomic_id(), METHOD_GET_TEMPLATE, params).into_req()
+}
+
+// the pre pow hash of the header is computed as the bridge, the target is the bits of it
+pub fn parse_template(form: &FormTemplate) -> UResult<Job> {
+    let header = serde_json::from_value::<FormHeader>(form.block["header"].clone())?.to_header()?;
+    let state = PowState::new(&header);
+
+    Ok(Job {
+        id: 0,
+        jobid: format!("{}:{:x}", header.daa_score, header.timestamp),
+        powhash: hash_override_nonce_time(&header, 0, 0),
+        target: state.target,
+        timestamp: header.timestamp,
+        nonce: 0,
+        nonce1_bytes: 0,
+        matrixhasher: Arc::new((state.matrix, state.hasher)),
+        block: Some(Arc::new(form.block.clone())),
+    })
+}
+
+// {"report":{"type":"success"}} or {"report":{"type":"reject","reason":"blockInvalid"}}
+pub fn parse_submit_report(resp: &Response) -> bool {
+    let report = &resp.result["report"];
+    let kind = report.get("type").unwrap_or(report).as_str();
+    resp.error.is_none() && kind.map(|k| k.eq_ignore_ascii_case("success")).unwrap_or(false)
+}
+
+// the block of the template with the nonce
+// {"id":9,"method":"submitBlock","params":{"block":{"header":{..,"nonce":1234},"transactions":[..]},"allowNonDaaBlocks":false}}
+fn make_submit_block(solution: &Solution, block: &Value) -> Req {
+    let mut block = block.clone();
+    block["header"]["nonce"] = solution.nonce.into();
+    let params = json!({ "block": block, "allowNonDaaBlocks": false });
+    Request::new(solution.id, METHOD_SUBMIT_BLOCK, params).into_req()
+}
+
+#[test]
+fn test_block_template() {
+    use crate::kas::pow::Computer;
+
+    // the header is serialized by rusty-kaspa 0.13.4 with the easiest bits, its block hash is the one of kaspad
+    let template = r#"{"id":3,"params":{"block":{"header":{"version":1,"parentsByLevel":[["6a5d1f6f4e8c27da5ffb3e0b5ba06e6d8ae18c1fa9f3e0bc04a0b69f0e3b1d22","0b6e0b0b56e0e4a3b3e1c4a69b7c5e4b3b8c0f1ad0e3e2a7e7f7b0c1b2a39485"],["6a5d1f6f4e8c27da5ffb3e0b5ba06e6d8ae18c1fa9f3e0bc04a0b69f0e3b1d22"]],"hashMerkleRoot":"1f2e3d4c5b6a79880f1e2d3c4b5a69788f9e0d1c2b3a49586f7e8d9c0b1a2938","acceptedIdMerkleRoot":"00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff","utxoCommitment":"ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100","timestamp":1700000000123,"bits":545259519,"nonce":4660,"daaScore":68000000,"blueWork":"0000000000000000000000000000000001c2b3a4d5e6f708","blueScore":66000000,"pruningPoint":"a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f90"},"transactions":[{"version":0,"inputs":[],"outputs":[],"lockTime":0,"subnetworkId":"0100000000000000000000000000000000000000","gas":0,"payload":"00"}]},"isSynced":true}}"#;
+    let resp = parse_rpc(template).unwrap();
+    assert_eq!((resp.id, resp.error.is_none()), (3, true));
+    let form = resp.result::<FormTemplate>().unwrap();
+    let mut job = parse_template(&form).unwrap();
+    assert_eq!(job.target, Uint256::from_compact_target_bits(0x207fffff));
+    assert_eq!(job.timestamp, 1700000000123);
+
+    let header = serde_json::from_value::<FormHeader>(form.block["header"].clone()).unwrap().to_header().unwrap();
+    assert_eq!(header.blue_work[16..], [1, 0xc2, 0xb3, 0xa4, 0xd5, 0xe6, 0xf7, 0x08]);
+    assert_eq!(job.powhash, hash_override_nonce_time(&header, 0, 0));
+    let block_hash = "6886c167db4dc98635e897fc3a1f8f18b8cfb6593327abbd0fb9f28df88391d5".parse::<Hash>().unwrap();
+    assert_eq!(hash_override_nonce_time(&header, 0x1234, header.timestamp), block_hash);
+
+    let mut computer = Computer::new(false);
+    job.nonce = 0x1234;
+    let solution = (job.nonce..).find_map(|n| computer.compute(&job, n)).unwrap();
+    let req = make_submit(&solution, &job).unwrap();
+    assert_eq!(req.1, METHOD_SUBMIT_BLOCK);
+
+    // the same block with the nonce, which is valid to the pow of the header
+    let submit = serde_json::from_str::<Value>(&req.2).unwrap();
+    let block = &submit["params"]["block"];
+    assert_eq!(block["header"]["nonce"], solution.nonce);
+    assert_eq!(block["transactions"], form.block["transactions"]);
+    let mut header = serde_json::from_value::<FormHeader>(block["header"].clone()).unwrap().to_header().unwrap();
+    header.nonce = solution.nonce;
+    assert!(PowState::new(&header).check_pow(header.nonce).0);
+
+    let report = |r: &str| parse_submit_report(&parse_rpc(r).unwrap());
+    assert!(report(r#"{"id":9,"params":{"report":{"type":"success"}}}"#));
+    assert!(!report(r#"{"id":9,"params":{"report":{"type":"reject","reason":"blockInvalid"}}}"#));
+    assert!(!report(r#"{"id":9,"error":{"message":"invalid block"}}"#));
+}
+
+#[test]
+fn test_genesis_header() {
+    // the header of the mainnet genesis as serialized by rusty-kaspa 0.13.4
+    let genesis = r#"{"hash":"58c2d4199e21f910d1571d114969cecef48f09f934d42ccb6a281a15868f2999","version":0,"parentsByLevel":[],"hashMerkleRoot":"8ec898568c6801d13df4ee6e2a1b54b7e6236f671f20954f05306410518eeb32","acceptedIdMerkleRoot":"0000000000000000000000000000000000000000000000000000000000000000","utxoCommitment":"710f27df423e63aa6cdb72b89ea5a06cffa399d66f167704455b5af59def8e20","timestamp":1637609671037,"bits":486722099,"nonce":211244,"daaScore":1312860,"blueWork":"000000000000000000000000000000000000000000000000","blueScore":0,"pruningPoint":"0000000000000000000000000000000000000000000000000000000000000000"}"#;
+    let json = serde_json::from_str::<Value>(genesis).unwrap();
+    let mut header = serde_json::from_value::<FormHeader>(json.clone()).unwrap().to_header().unwrap();
+    header.nonce = json["nonce"].as_u64().unwrap();
+    assert_eq!(header.hash(), json["hash"].as_str().unwrap().parse::<Hash>().unwrap());
+}
@@ -0,0 +1,191 @@
+// the hash kernels are selected at runtime by the cpu features, so one binary runs on the heterogeneous hosts
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::config::Currency;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuFeatures {
+    pub sse41: bool,
+    pub avx2: bool,
+    pub avx512: bool,
+    pub sha: bool,
+}
+
+impl CpuFeatures {
+    #[cfg(target_arch = "x86_64")]
+    pub fn detect() -> Self {
+        Self {
+            sse41: is_x86_feature_detected!("sse4.1"),
+            avx2: is_x86_feature_detected!("avx2"),
+            avx512: is_x86_feature_detected!("avx512f"),
+            sha: is_x86_feature_detected!("sha"),
+        }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn detect() -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Display for CpuFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let features = [(self.sse41, "sse4.1"), (self.avx2, "avx2"), (self.avx512, "avx512f"), (self.sha, "sha")];
+        let names = features.iter().filter(|(b, _)| *b).map(|(_, n)| *n).collect::<Vec<_>>();
+        if names.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&names.join(" "))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    Scalar,
+    Sse2,
+    Avx2,
+    Avx512,
+    ShaNi,
+    Asm,
+}
+
+impl Kernel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Scalar => "scalar",
+            Self::Sse2 => "sse2",
+            Self::Avx2 => "avx2",
+            Self::Avx512 => "avx512",
+            Self::ShaNi => "sha-ni",
+            Self::Asm => "asm",
+        }
+    }
+}
+
+impl fmt::Display for Kernel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256d,
+    Eaglesong,
+    Keccak,
+    Ethash,
+}
+
+impl Algorithm {
+    pub const ALL: [Self; 4] = [Self::Sha256d, Self::Eaglesong, Self::Keccak, Self::Ethash];
+
+    pub fn of(currency: Currency) -> Self {
+        match currency {
+            Currency::Btc => Self::Sha256d,
+            Currency::Ckb => Self::Eaglesong,
+            Currency::Kas => Self::Keccak,
+            Currency::Eth => Self::Ethash,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256d => "sha256d",
+            Self::Eaglesong => "eaglesong",
+            Self::Keccak => "keccak",
+            Self::Ethash => "ethash",
+        }
+    }
+    // the kernels work on the cpu, the fastest first, eaglesong(ckb) and ethash(eth) are scalar only
+    pub fn kernels(&self, cpu: &CpuFeatures) -> Vec<Kernel> {
+        let mut kernels = vec![];
+        match self {
+            Self::Sha256d if cfg!(target_arch = "x86_64") => {
+                if cpu.avx512 {
+                    kernels.push(Kernel::Avx512);
+                }
+                if cpu.sha && cpu.sse41 {
+                    kernels.push(Kernel::ShaNi);
+                }
+                if cpu.avx2 {
+                    kernels.push(Kernel::Avx2);
+                }
+                kernels.push(Kernel::Sse2);
+            }
+            // the asm of cryptogams is about 1.3x of the keccak crate
+            Self::Keccak if kaspow::keccak256::ASM => kernels.push(Kernel::Asm),
+            _ => {}
+        }
+        kernels.push(Kernel::Scalar);
+        kernels
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Kernels {
+    pub cpu: CpuFeatures,
+    pub selected: Vec<(Algorithm, Kernel)>,
+}
+
+impl Kernels {
+    // the fastest kernels, or the kernel of the name for the algorithm of currency
+    pub fn select(currency: Currency, name: Option<&str>) -> Result<Self, String> {
+        let cpu = CpuFeatures::detect();
+        let mut selected = Vec::with_capacity(Algorithm::ALL.len());
+        for algo in Algorithm::ALL.iter().copied() {
+            let kernels = algo.kernels(&cpu);
+            let kernel = match name {
+                Some(name) if algo == Algorithm::of(currency) => kernels.iter().find(|k| k.name() == name).copied().ok_or_else(|| match kernels.as_slice() {
+                    [Kernel::Scalar] => format!("the kernel {} isn't implemented for {} of {:?}, it only has the scalar kernel", name, algo.name(), currency),
+                    _ => {
+                        let names = kernels.iter().map(|k| k.name()).collect::<Vec<_>>();
+                        format!("the kernel {} of {} isn't supported by the cpu({}), the available: {}", name, algo.name(), cpu, names.join(", "))
+                    }
+                })?,
+                _ => kernels[0],
+            };
+            selected.push((algo, kernel));
+        }
+        Ok(Self { cpu, selected })
+    }
+    pub fn get(&self, algo: Algorithm) -> Kernel {
+        self.selected.iter().find(|(a, _)| *a == algo).map(|(_, k)| *k).unwrap_or(Kernel::Scalar)
+    }
+}
+
+impl fmt::Display for Kernels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cpu features: {}, kernels:", self.cpu)?;
+        for (algo, kernel) in &self.selected {
+            write!(f, " {}={}", algo.name(), kernel)?;
+        }
+        Ok(())
+    }
+}
+
+static KERNELS: OnceLock<Kernels> = OnceLock::new();
+
+// selects the kernels before the workers start, the name is checked by the config
+pub fn init(currency: Currency, name: Option<&str>) -> &'static Kernels {
+    let kernels = KERNELS.get_or_init(|| Kernels::select(currency, name).expect("select kernels"));
+    kaspow::keccak256::use_asm(kernels.get(Algorithm::Keccak) == Kernel::Asm);
+    kernels
+}
+
+// the fastest kernel if not initialized, such as the tests
+pub fn get(algo: Algorithm) -> Kernel {
+    KERNELS.get_or_init(|| Kernels::select(Currency::Ckb, None).expect("select kernels")).get(algo)
+}
+
+#[test]
+fn test_select_kernels() {
+    let kernels = Kernels::select(Currency::Btc, None).unwrap();
+    assert_eq!(kernels.get(Algorithm::Sha256d), Algorithm::Sha256d.kernels(&kernels.cpu)[0]);
+    assert_eq!(kernels.get(Algorithm::Eaglesong), Kernel::Scalar);
+
+    let kernels = Kernels::select(Currency::Btc, Some("scalar")).unwrap();
+    assert_eq!(kernels.get(Algorithm::Sha256d), Kernel::Scalar);
+    assert!(Kernels::select(Currency::Ckb, Some("avx2")).unwrap_err().contains("only has the scalar kernel"));
+    assert_eq!(Kernels::select(Currency::Ckb, Some("scalar")).unwrap().get(Algorithm::Eaglesong), Kernel::Scalar);
+    assert!(Kernels::select(Currency::Btc, Some("unknown")).is_err());
+}
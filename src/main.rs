@@ -32,12 +32,17 @@ pub fn format(base: &BaseFormater, record: &Record) -> String {
 }
 
 fn main() {
-    use clap::Parser;
+    let config = Config::load();
+    if config.print_config {
+        print!("{}", config.to_toml(config.print_secrets));
+        return;
+    }
 
-    let config = Config::parse().fix_workers();
+    let config = config.fix_workers();
     let pkg = env!("CARGO_PKG_NAME");
     let log = config.log();
     println!("{}: {:?}, {:?}", pkg, log, config);
+    println!("{}: {}", pkg, kernel::init(config.currency, config.kernel.as_deref()));
 
     let formater = BaseFormater::new().local(true).color(true).level(4).formater(format);
     let filter = BaseFilter::new()
@@ -62,10 +67,15 @@ fn main() {
     fun(config)
 }
 
+pub mod chain;
 pub mod config;
+pub mod http;
+pub mod kernel;
 pub mod miner;
 pub mod reqs;
 pub mod state;
+pub mod stratum;
+pub mod sv2;
 pub mod util;
 
 pub mod btc;
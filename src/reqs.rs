@@ -10,6 +10,8 @@ pub struct Req {
     pub id: usize,
     pub method: &'static str,
     pub time: Instant,
+    // overrides the timeout of the sweep
+    pub timeout: Option<Duration>,
 }
 
 impl From<&RawReq> for Req {
@@ -18,6 +20,7 @@ impl From<&RawReq> for Req {
             id: raw.0,
             method: raw.1,
             time: Instant::now(),
+            timeout: None,
         }
     }
 }
@@ -38,9 +41,26 @@ impl Reqs {
         let req = req.into();
         self.data.insert(req.id, req)
     }
+    // the request waits longer than the others, such as the shares acknowledged in batch
+    pub fn add_with_timeout<R>(&mut self, req: R, timeout: Duration) -> Option<Req>
+    where
+        R: Into<Req>,
+    {
+        let mut req = req.into();
+        req.timeout = Some(timeout);
+        self.data.insert(req.id, req)
+    }
+    pub fn get(&self, id: usize) -> Option<&Req> {
+        self.data.get(&id)
+    }
     pub fn remove(&mut self, id: usize) -> Option<Req> {
         self.data.remove(&id)
     }
+    // the requests of the method up to the id, such as the shares acknowledged in batch
+    pub fn remove_until(&mut self, id: usize, method: &str) -> Vec<Req> {
+        let ids = self.data.range(..=id).filter(|(_, req)| req.method == method).map(|(k, _)| *k).collect::<Vec<_>>();
+        ids.into_iter().filter_map(|k| self.remove(k)).collect()
+    }
     pub fn clear_timeouts<F>(&mut self, timeout: &Duration, f: F) -> usize
     where
         F: Fn(Req, Duration),
@@ -50,7 +70,7 @@ impl Reqs {
             .values()
             .filter_map(|req| {
                 let d = req.time.elapsed();
-                if d >= *timeout {
+                if d >= req.timeout.unwrap_or(*timeout) {
                     Some((req.id, d))
                 } else {
                     None
@@ -61,3 +81,23 @@ impl Reqs {
         kds.into_iter().map(|(k, d)| self.remove(k).map(|req| f(req, d))).count()
     }
 }
+
+#[test]
+fn test_reqs_timeout() {
+    let mut reqs = Reqs::new();
+    let ago = Instant::now() - Duration::from_secs(10);
+    for id in 1..=3 {
+        reqs.add_with_timeout(&RawReq(id, "SubmitSharesStandard", String::new()), Duration::from_secs(60));
+        reqs.data.get_mut(&id).unwrap().time = ago;
+    }
+    reqs.add(&RawReq(4, "mining.submit", String::new()));
+    reqs.data.get_mut(&4).unwrap().time = ago;
+
+    // only the one of the default timeout is cleared
+    assert_eq!(reqs.clear_timeouts(&Duration::from_secs(3), |req, _| assert_eq!(req.id, 4)), 1);
+    // the late ack of the batch
+    assert_eq!(reqs.remove_until(2, "SubmitSharesStandard").len(), 2);
+    assert_eq!(reqs.clear_timeouts(&Duration::from_secs(3), |_, _| {}), 0);
+    reqs.data.get_mut(&3).unwrap().time = Instant::now() - Duration::from_secs(60);
+    assert_eq!(reqs.clear_timeouts(&Duration::from_secs(3), |req, _| assert_eq!(req.id, 3)), 1);
+}
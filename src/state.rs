@@ -3,12 +3,12 @@ use parking_lot::Mutex;
 use tokio::sync::mpsc;
 
 use std::sync::{
-    atomic::{AtomicUsize, Ordering},
+    atomic::{AtomicU64, AtomicUsize, Ordering},
     Arc,
 };
-use std::thread;
+use std::{thread, time::Duration};
 
-use crate::config::{timeout, Config};
+use crate::config::{timeout, Config, Pool};
 use crate::reqs::Reqs;
 use crate::util::{self, DescError};
 
@@ -50,11 +50,14 @@ impl Counter {
 }
 
 pub trait Job: Clone + Default + std::fmt::Debug + Send + 'static {
+    // built from the config for each state, such as the dags of eth
+    type Shared: std::fmt::Debug + Send + Sync + 'static;
+    fn shared(config: &Config) -> Self::Shared;
     fn jobid(&self) -> String;
 }
 
 #[derive(Debug)]
-pub struct Worker<C> {
+pub struct Worker<C: Job> {
     pub testnet: bool,
     pub job: State<C>,
     pub jobsc: Counter,
@@ -121,18 +124,82 @@ impl<C: Default> Default for Statev<C> {
     }
 }
 
+// the pools in order of priority, the first one is the primary, and the reconnecting metric of the net thread
+#[derive(Debug)]
+pub struct Pools {
+    pools: Vec<Pool>,
+    current: AtomicUsize,
+    reconnects: AtomicUsize,
+    backoff_ms: AtomicU64,
+}
+
+impl Pools {
+    pub fn new(pools: Vec<Pool>) -> Self {
+        assert!(!pools.is_empty(), "pools is empty");
+        Self {
+            pools,
+            current: AtomicUsize::new(0),
+            reconnects: AtomicUsize::new(0),
+            backoff_ms: AtomicU64::new(0),
+        }
+    }
+    pub fn count(&self) -> usize {
+        self.pools.len()
+    }
+    pub fn has_failover(&self) -> bool {
+        self.pools.len() > 1
+    }
+    pub fn primary(&self) -> &Pool {
+        &self.pools[0]
+    }
+    pub fn current(&self) -> (usize, &Pool) {
+        let idx = self.current.load(Ordering::Relaxed);
+        (idx, &self.pools[idx])
+    }
+    pub fn switch(&self, idx: usize) -> &Pool {
+        let idx = idx % self.pools.len();
+        self.current.store(idx, Ordering::SeqCst);
+        &self.pools[idx]
+    }
+    pub fn next(&self) -> &Pool {
+        self.switch(self.current().0 + 1)
+    }
+    pub fn reconnecting(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn set_backoff(&self, backoff: Duration) {
+        self.backoff_ms.store(backoff.as_millis() as _, Ordering::Relaxed);
+    }
+    // (reconnects, the current backoff)
+    pub fn reconnect_metric(&self) -> (usize, Duration) {
+        (self.reconnects.load(Ordering::Relaxed), Duration::from_millis(self.backoff_ms.load(Ordering::Relaxed)))
+    }
+}
+
+type StateInner<C> = (Mutex<Statev<C>>, Config, ReqSender, Pools, <C as Job>::Shared);
+
 #[derive(Debug, Clone)]
-pub struct State<C>(Arc<(Mutex<Statev<C>>, Config, ReqSender)>);
+pub struct State<C: Job>(Arc<StateInner<C>>);
 
-impl<C: Default> State<C> {
+impl<C: Job> State<C> {
     pub fn new(config: Config, mp: ReqSender) -> Self {
-        Self(Arc::new((Mutex::new(Statev::new()), config, mp)))
+        let pools = Pools::new(config.pools());
+        let shared = C::shared(&config);
+        Self(Arc::new((Mutex::new(Statev::new()), config, mp, pools, shared)))
+    }
+    pub fn shared(&self) -> &C::Shared {
+        &(self.0).4
     }
 }
 
 pub trait Handle: Clone + std::fmt::Debug + Send + Sized + 'static {
-    fn login_request(&self) -> Req;
+    // sent in order once connected, each with a unique id
+    fn login_request(&self) -> Vec<Req>;
     fn hashrate_request(&self, hashrate: u64) -> Option<Req>;
+    // sent every --poll millis to the node of http(s)/wrpc(s):// pool, which has no push
+    fn poll_request(&self) -> Option<Req> {
+        None
+    }
     fn handle_request(&self, req: Req) -> util::Result<String>;
     fn handle_response(&self, _resp: String) -> util::Result<()>;
 }
@@ -141,6 +208,10 @@ pub trait Handler<C>: Handle {
     fn config(&self) -> &Config;
     fn value(&self) -> &Mutex<Statev<C>>;
     fn sender(&self) -> &ReqSender;
+    fn pools(&self) -> &Pools;
+    fn pool(&self) -> &Pool {
+        self.pools().current().1
+    }
     fn jobid(&self) -> Option<String>;
     fn start_workers(&self);
     fn try_show_metric(&self, secs: u64) -> bool;
@@ -161,6 +232,9 @@ where
     fn sender(&self) -> &ReqSender {
         &(self.0).2
     }
+    fn pools(&self) -> &Pools {
+        &(self.0).3
+    }
     fn jobid(&self) -> Option<String> {
         self.value().try_lock().map(|l| (*l).job.jobid())
     }
@@ -174,7 +248,7 @@ where
             lock.hashrates.push(hashrate.clone());
 
             let mut worker = Worker {
-                testnet: self.config().testnet,
+                testnet: self.config().chain().is_testnet(),
                 sleep: self.config().sleep,
                 job: (*self).clone(),
                 jobsc: lock.jobsc.clone(),
@@ -201,13 +275,16 @@ where
                 let secs = if secs > 0 { secs } else { 1 };
                 let hashrate = (m.hashrate as u64) / secs;
 
+                let (reconnects, backoff) = self.pools().reconnect_metric();
                 info!(
-                    "hashrate: {}, jobs: {}, submit: {}, accepted: {}, rejected: {}",
+                    "hashrate: {}, jobs: {}, submit: {}, accepted: {}, rejected: {}, reconnects: {}, backoff: {:?}",
                     ByteSize(hashrate),
                     m.jobsc,
                     m.submitc,
                     m.acceptc,
-                    m.rejectc
+                    m.rejectc,
+                    reconnects,
+                    backoff
                 );
 
                 hashrate
@@ -0,0 +1,213 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use std::{convert::TryFrom, time::Duration};
+
+use crate::state::{Handler, Req};
+use crate::util::Result;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const METHOD_RECONNECT: &str = "client.reconnect";
+pub const METHOD_SHOW_MESSAGE: &str = "client.show_message";
+pub const METHOD_PING: &str = "mining.ping";
+pub const METHOD_SET_EXTRANONCE: &str = "mining.set_extranonce";
+// the method of the replies to the requests of pool, they are not tracked by the reqs
+pub const METHOD_REPLY: &str = "reply";
+
+// {"id":8,"method":"mining.submit","params":["sp_yos.cpux","Jy4KZryMoz0=","0400000000000000","5e968131","24b9d8f9"]}
+// the optional fields are the extensions of some dialects, such as eth-proxy
+#[derive(Debug, Clone, Serialize)]
+pub struct Request<'a, P> {
+    pub id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jsonrpc: Option<&'static str>,
+    pub method: &'static str,
+    pub params: P,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compact: Option<bool>,
+}
+
+impl<'a, P: Serialize> Request<'a, P> {
+    pub fn new(id: usize, method: &'static str, params: P) -> Self {
+        Self {
+            id,
+            jsonrpc: None,
+            method,
+            params,
+            worker: None,
+            compact: None,
+        }
+    }
+    pub fn jsonrpc(mut self) -> Self {
+        self.jsonrpc = Some(JSONRPC_VERSION);
+        self
+    }
+    pub fn worker(mut self, worker: &'a str) -> Self {
+        self.worker = Some(worker);
+        self
+    }
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.compact = Some(compact);
+        self
+    }
+    pub fn into_req(self) -> Req {
+        let json = serde_json::to_string(&self).expect("stratum request to json");
+        Req(self.id, self.method, json)
+    }
+}
+
+// {"id":null,"method":"mining.set_difficulty","params":[1]}
+#[derive(Debug, Clone)]
+pub struct Notify {
+    pub id: Option<usize>,
+    pub method: String,
+    pub params: Value,
+}
+
+impl Notify {
+    pub fn params<T: DeserializeOwned>(self) -> Result<T> {
+        let method = self.method;
+        serde_json::from_value(self.params).map_err(|e| format_err!("invalid params of {}: {}", method, e))
+    }
+}
+
+// {"id":8,"result":true,"error":null}
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub id: usize,
+    pub result: Value,
+    pub error: Option<Value>,
+}
+
+impl Response {
+    pub fn result<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(self.result.clone()).map_err(|e| format_err!("invalid result of {}: {}", self.id, e))
+    }
+    // {"id":2,"result":null,"error":[24,"unauthorized worker",null]} is false too
+    pub fn to_bool(&self) -> Option<bool> {
+        match &self.result {
+            Value::Bool(b) => Some(*b),
+            Value::Null => Some(false),
+            _ => None,
+        }
+    }
+    pub fn error_string(&self) -> Option<String> {
+        self.error.as_ref().map(|e| e.to_string())
+    }
+}
+
+// {"id":3,"result":"pong","error":null}
+#[derive(Debug, Clone, Serialize)]
+struct Reply<R> {
+    id: usize,
+    result: R,
+    error: (),
+}
+
+pub fn make_reply<R: Serialize>(id: usize, result: R) -> Req {
+    let json = serde_json::to_string(&Reply { id, result, error: () }).expect("stratum reply to json");
+    Req(id, METHOD_REPLY, json)
+}
+
+// the pool asks to reconnect, to another address if any
+#[derive(Debug, Error)]
+#[error("{} to {:?}:{:?} after {:?}", METHOD_RECONNECT, host, port, wait)]
+pub struct ReconnectError {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub wait: Duration,
+}
+
+// the params may be numbers or strings: ["stratum.example.com", 3333, 0], ["", "3333", "10"] or []
+fn reconnect_params(params: &Value) -> ReconnectError {
+    let get = |idx: usize| params.as_array().and_then(|a| a.get(idx));
+    let num = |idx: usize| get(idx).and_then(|v| v.as_u64().or_else(|| v.as_str().and_then(|s| s.parse().ok())));
+    ReconnectError {
+        host: get(0).and_then(|v| v.as_str()).filter(|h| !h.is_empty()).map(|h| h.to_owned()),
+        port: num(1).and_then(|p| u16::try_from(p).ok()).filter(|p| *p > 0),
+        wait: Duration::from_secs(num(2).unwrap_or(0)),
+    }
+}
+
+// handles the notifications shared by all the coins, the others are returned for the coin
+pub fn handle_notify<C, S: Handler<C>>(state: &S, notify: Notify) -> Result<Option<Notify>> {
+    match notify.method.as_str() {
+        METHOD_RECONNECT => Err(reconnect_params(&notify.params).into()),
+        METHOD_SHOW_MESSAGE => {
+            warn!("message from the pool: {}", notify.params);
+            Ok(None)
+        }
+        METHOD_PING => {
+            debug!("{} from the pool, id: {:?}", METHOD_PING, notify.id);
+            if let Some(id) = notify.id {
+                state.sender().clone().try_send(Ok(make_reply(id, "pong"))).map_err(|e| error!("try send pong failed: {:?}", e)).ok();
+            }
+            Ok(None)
+        }
+        _ => Ok(Some(notify)),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Notify(Notify),
+    Response(Response),
+}
+
+#[derive(Debug, Deserialize)]
+struct RawMessage {
+    #[serde(default)]
+    id: Value,
+    method: Option<String>,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    result: Value,
+    #[serde(default)]
+    error: Value,
+}
+
+// a message with the method is a notification even if it has an id, such as {"id":3,"method":"mining.set_extranonce","params":["3d9e"]}
+pub fn parse(line: &str) -> Result<Message> {
+    let raw: RawMessage = serde_json::from_str(line)?;
+    let id = raw.id.as_u64().map(|id| id as usize);
+    match (raw.method, id) {
+        (Some(method), _) => Ok(Message::Notify(Notify { id, method, params: raw.params })),
+        (None, Some(id)) => Ok(Message::Response(Response {
+            id,
+            result: raw.result,
+            error: Some(raw.error).filter(|e| !e.is_null()),
+        })),
+        (None, None) => Err(format_err!("neither a notification nor a response")),
+    }
+}
+
+#[test]
+fn test_stratum_codec() {
+    let req = Request::new(8, "mining.authorize", ["sp\"yos.cpu\\x", "x"]).into_req();
+    assert_eq!(req.2, r#"{"id":8,"method":"mining.authorize","params":["sp\"yos.cpu\\x","x"]}"#);
+    let req = Request::new(1, "eth_submitLogin", ["sp_yos.0v0"]).worker("0v0").compact(true).into_req();
+    assert_eq!(req.2, r#"{"id":1,"method":"eth_submitLogin","params":["sp_yos.0v0"],"worker":"0v0","compact":true}"#);
+
+    match parse(r#"{"id":null,"method":"mining.set_difficulty","params":[2]}"#).unwrap() {
+        Message::Notify(n) => assert_eq!(n.params::<(u64,)>().unwrap(), (2,)),
+        m => panic!("{:?}", m),
+    }
+    match parse(r#"{"id":2,"result":null,"error":[24,"unauthorized worker",null]}"#).unwrap() {
+        Message::Response(r) => {
+            assert_eq!((r.id, r.to_bool()), (2, Some(false)));
+            assert_eq!(r.error_string().unwrap(), r#"[24,"unauthorized worker",null]"#);
+        }
+        m => panic!("{:?}", m),
+    }
+    assert!(parse(r#"{"result":true}"#).is_err());
+
+    assert_eq!(make_reply(3, "pong").2, r#"{"id":3,"result":"pong","error":null}"#);
+    let e = reconnect_params(&serde_json::json!(["stratum.example.com", "3334", 5]));
+    assert_eq!((e.host.as_deref(), e.port, e.wait), (Some("stratum.example.com"), Some(3334), Duration::from_secs(5)));
+    let e = reconnect_params(&serde_json::json!([]));
+    assert_eq!((e.host, e.port, e.wait), (None, None, Duration::from_secs(0)));
+}
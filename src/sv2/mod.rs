@@ -0,0 +1,647 @@
+// the stratum v2 mining protocol: the binary messages in the frames encrypted by the noise transport,
+// the messages are passed to the handlers as json, so they plug into the same Handler as the v1 ones
+use bitcoin::secp256k1::schnorrsig;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use std::convert::{TryFrom, TryInto};
+
+pub mod ellswift;
+pub mod noise;
+
+use crate::util::Result;
+use noise::{Cipher, Initiator, ACT2_SIZE, MAC_SIZE};
+
+// extension_type(2), msg_type(1) and msg_length(3)
+pub const HEADER_SIZE: usize = 6;
+// the msb of extension_type, set for the messages of a channel
+const CHANNEL_BIT: u16 = 0x8000;
+// the encrypted payload is split into the chunks, each with its mac
+const MAX_CHUNK_SIZE: usize = 65535;
+
+pub const PROTOCOL_MINING: u8 = 0;
+pub const PROTOCOL_VERSION: u16 = 2;
+// the flags of SetupConnection for the mining protocol
+pub const REQUIRES_STANDARD_JOBS: u32 = 1;
+
+const SETUP_CONNECTION: u8 = 0x00;
+const SETUP_CONNECTION_SUCCESS: u8 = 0x01;
+const SETUP_CONNECTION_ERROR: u8 = 0x02;
+const OPEN_STANDARD_MINING_CHANNEL: u8 = 0x10;
+const OPEN_STANDARD_MINING_CHANNEL_SUCCESS: u8 = 0x11;
+const OPEN_MINING_CHANNEL_ERROR: u8 = 0x12;
+const NEW_MINING_JOB: u8 = 0x15;
+const SUBMIT_SHARES_STANDARD: u8 = 0x1a;
+const SUBMIT_SHARES_SUCCESS: u8 = 0x1c;
+const SUBMIT_SHARES_ERROR: u8 = 0x1d;
+const SET_NEW_PREV_HASH: u8 = 0x20;
+const SET_TARGET: u8 = 0x21;
+const RECONNECT: u8 = 0x25;
+
+// the U256 fields are hex of the bytes on the wire(little endian), STR0_255 and B0_32 are utf8 and hex
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    SetupConnection {
+        protocol: u8,
+        min_version: u16,
+        max_version: u16,
+        flags: u32,
+        endpoint_host: String,
+        endpoint_port: u16,
+        vendor: String,
+        hardware_version: String,
+        firmware: String,
+        device_id: String,
+    },
+    SetupConnectionSuccess {
+        used_version: u16,
+        flags: u32,
+    },
+    SetupConnectionError {
+        flags: u32,
+        error_code: String,
+    },
+    OpenStandardMiningChannel {
+        request_id: u32,
+        user_identity: String,
+        nominal_hash_rate: f32,
+        max_target: String,
+    },
+    OpenStandardMiningChannelSuccess {
+        request_id: u32,
+        channel_id: u32,
+        target: String,
+        extranonce_prefix: String,
+        group_channel_id: u32,
+    },
+    OpenMiningChannelError {
+        request_id: u32,
+        error_code: String,
+    },
+    // the future job without min_ntime waits for the SetNewPrevHash of its job_id
+    NewMiningJob {
+        channel_id: u32,
+        job_id: u32,
+        min_ntime: Option<u32>,
+        version: u32,
+        merkle_root: String,
+    },
+    SetNewPrevHash {
+        channel_id: u32,
+        job_id: u32,
+        prev_hash: String,
+        min_ntime: u32,
+        nbits: u32,
+    },
+    SetTarget {
+        channel_id: u32,
+        maximum_target: String,
+    },
+    SubmitSharesStandard {
+        channel_id: u32,
+        sequence_number: u32,
+        job_id: u32,
+        nonce: u32,
+        ntime: u32,
+        version: u32,
+    },
+    // the shares up to the last_sequence_number are acknowledged in batch
+    SubmitSharesSuccess {
+        channel_id: u32,
+        last_sequence_number: u32,
+        new_submits_accepted_count: u32,
+        new_shares_sum: u64,
+    },
+    SubmitSharesError {
+        channel_id: u32,
+        sequence_number: u32,
+        error_code: String,
+    },
+    Reconnect {
+        new_host: String,
+        new_port: u16,
+    },
+    // the messages not used by cminer
+    Unknown {
+        extension_type: u16,
+        msg_type: u8,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub extension_type: u16,
+    pub msg_type: u8,
+    pub payload: Vec<u8>,
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.0.push(n)
+    }
+    fn u16(&mut self, n: u16) {
+        self.0.extend_from_slice(&n.to_le_bytes())
+    }
+    fn u32(&mut self, n: u32) {
+        self.0.extend_from_slice(&n.to_le_bytes())
+    }
+    fn u64(&mut self, n: u64) {
+        self.0.extend_from_slice(&n.to_le_bytes())
+    }
+    fn f32(&mut self, n: f32) {
+        self.0.extend_from_slice(&n.to_le_bytes())
+    }
+    fn str0_255(&mut self, s: &str) -> Result<()> {
+        let len = u8::try_from(s.len()).map_err(|_| format_err!("STR0_255 too long: {}", s))?;
+        self.u8(len);
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+    fn b0_32(&mut self, s: &str) -> Result<()> {
+        let bytes = hex::decode(s)?;
+        if bytes.len() > 32 {
+            return Err(format_err!("B0_32 too long: {}", s));
+        }
+        self.u8(bytes.len() as _);
+        self.0.extend_from_slice(&bytes);
+        Ok(())
+    }
+    fn u256(&mut self, s: &str) -> Result<()> {
+        let mut bytes = [0u8; 32];
+        faster_hex::hex_decode(s.as_bytes(), &mut bytes).map_err(|e| format_err!("invalid U256 {}: {}", s, e))?;
+        self.0.extend_from_slice(&bytes);
+        Ok(())
+    }
+    fn option_u32(&mut self, n: Option<u32>) {
+        match n {
+            Some(n) => {
+                self.u8(1);
+                self.u32(n)
+            }
+            None => self.u8(0),
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(format_err!("sv2 message too short: {} < {}", self.0.len(), n));
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+    fn str0_255(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+    fn b0_32(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        if len > 32 {
+            return Err(format_err!("B0_32 too long: {}", len));
+        }
+        Ok(hex::encode(self.take(len)?))
+    }
+    fn u256(&mut self) -> Result<String> {
+        Ok(hex::encode(self.take(32)?))
+    }
+    fn option_u32(&mut self) -> Result<Option<u32>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => self.u32().map(Some),
+            b => Err(format_err!("invalid OPTION: {}", b)),
+        }
+    }
+}
+
+impl Message {
+    pub fn encode(&self) -> Result<Frame> {
+        let mut w = Writer(vec![]);
+        let (msg_type, channel) = match self {
+            Self::SetupConnection {
+                protocol,
+                min_version,
+                max_version,
+                flags,
+                endpoint_host,
+                endpoint_port,
+                vendor,
+                hardware_version,
+                firmware,
+                device_id,
+            } => {
+                w.u8(*protocol);
+                w.u16(*min_version);
+                w.u16(*max_version);
+                w.u32(*flags);
+                w.str0_255(endpoint_host)?;
+                w.u16(*endpoint_port);
+                w.str0_255(vendor)?;
+                w.str0_255(hardware_version)?;
+                w.str0_255(firmware)?;
+                w.str0_255(device_id)?;
+                (SETUP_CONNECTION, false)
+            }
+            Self::SetupConnectionSuccess { used_version, flags } => {
+                w.u16(*used_version);
+                w.u32(*flags);
+                (SETUP_CONNECTION_SUCCESS, false)
+            }
+            Self::SetupConnectionError { flags, error_code } => {
+                w.u32(*flags);
+                w.str0_255(error_code)?;
+                (SETUP_CONNECTION_ERROR, false)
+            }
+            Self::OpenStandardMiningChannel {
+                request_id,
+                user_identity,
+                nominal_hash_rate,
+                max_target,
+            } => {
+                w.u32(*request_id);
+                w.str0_255(user_identity)?;
+                w.f32(*nominal_hash_rate);
+                w.u256(max_target)?;
+                (OPEN_STANDARD_MINING_CHANNEL, false)
+            }
+            Self::OpenStandardMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_prefix,
+                group_channel_id,
+            } => {
+                w.u32(*request_id);
+                w.u32(*channel_id);
+                w.u256(target)?;
+                w.b0_32(extranonce_prefix)?;
+                w.u32(*group_channel_id);
+                (OPEN_STANDARD_MINING_CHANNEL_SUCCESS, false)
+            }
+            Self::OpenMiningChannelError { request_id, error_code } => {
+                w.u32(*request_id);
+                w.str0_255(error_code)?;
+                (OPEN_MINING_CHANNEL_ERROR, false)
+            }
+            Self::NewMiningJob {
+                channel_id,
+                job_id,
+                min_ntime,
+                version,
+                merkle_root,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*job_id);
+                w.option_u32(*min_ntime);
+                w.u32(*version);
+                w.b0_32(merkle_root)?;
+                (NEW_MINING_JOB, true)
+            }
+            Self::SetNewPrevHash {
+                channel_id,
+                job_id,
+                prev_hash,
+                min_ntime,
+                nbits,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*job_id);
+                w.u256(prev_hash)?;
+                w.u32(*min_ntime);
+                w.u32(*nbits);
+                (SET_NEW_PREV_HASH, true)
+            }
+            Self::SetTarget { channel_id, maximum_target } => {
+                w.u32(*channel_id);
+                w.u256(maximum_target)?;
+                (SET_TARGET, true)
+            }
+            Self::SubmitSharesStandard {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*sequence_number);
+                w.u32(*job_id);
+                w.u32(*nonce);
+                w.u32(*ntime);
+                w.u32(*version);
+                (SUBMIT_SHARES_STANDARD, true)
+            }
+            Self::SubmitSharesSuccess {
+                channel_id,
+                last_sequence_number,
+                new_submits_accepted_count,
+                new_shares_sum,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*last_sequence_number);
+                w.u32(*new_submits_accepted_count);
+                w.u64(*new_shares_sum);
+                (SUBMIT_SHARES_SUCCESS, true)
+            }
+            Self::SubmitSharesError {
+                channel_id,
+                sequence_number,
+                error_code,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*sequence_number);
+                w.str0_255(error_code)?;
+                (SUBMIT_SHARES_ERROR, true)
+            }
+            Self::Reconnect { new_host, new_port } => {
+                w.str0_255(new_host)?;
+                w.u16(*new_port);
+                (RECONNECT, false)
+            }
+            Self::Unknown { .. } => return Err(format_err!("encode unknown sv2 message: {:?}", self)),
+        };
+
+        Ok(Frame {
+            extension_type: if channel { CHANNEL_BIT } else { 0 },
+            msg_type,
+            payload: w.0,
+        })
+    }
+    pub fn decode(frame: &Frame) -> Result<Self> {
+        let r = &mut Reader(&frame.payload);
+        let extension_type = frame.extension_type & !CHANNEL_BIT;
+        if extension_type != 0 {
+            return Ok(Self::Unknown {
+                extension_type,
+                msg_type: frame.msg_type,
+            });
+        }
+
+        let msg = match frame.msg_type {
+            SETUP_CONNECTION => Self::SetupConnection {
+                protocol: r.u8()?,
+                min_version: r.u16()?,
+                max_version: r.u16()?,
+                flags: r.u32()?,
+                endpoint_host: r.str0_255()?,
+                endpoint_port: r.u16()?,
+                vendor: r.str0_255()?,
+                hardware_version: r.str0_255()?,
+                firmware: r.str0_255()?,
+                device_id: r.str0_255()?,
+            },
+            SETUP_CONNECTION_SUCCESS => Self::SetupConnectionSuccess {
+                used_version: r.u16()?,
+                flags: r.u32()?,
+            },
+            SETUP_CONNECTION_ERROR => Self::SetupConnectionError {
+                flags: r.u32()?,
+                error_code: r.str0_255()?,
+            },
+            OPEN_STANDARD_MINING_CHANNEL => Self::OpenStandardMiningChannel {
+                request_id: r.u32()?,
+                user_identity: r.str0_255()?,
+                nominal_hash_rate: r.f32()?,
+                max_target: r.u256()?,
+            },
+            OPEN_STANDARD_MINING_CHANNEL_SUCCESS => Self::OpenStandardMiningChannelSuccess {
+                request_id: r.u32()?,
+                channel_id: r.u32()?,
+                target: r.u256()?,
+                extranonce_prefix: r.b0_32()?,
+                group_channel_id: r.u32()?,
+            },
+            OPEN_MINING_CHANNEL_ERROR => Self::OpenMiningChannelError {
+                request_id: r.u32()?,
+                error_code: r.str0_255()?,
+            },
+            NEW_MINING_JOB => Self::NewMiningJob {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                min_ntime: r.option_u32()?,
+                version: r.u32()?,
+                merkle_root: r.b0_32()?,
+            },
+            SET_NEW_PREV_HASH => Self::SetNewPrevHash {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                prev_hash: r.u256()?,
+                min_ntime: r.u32()?,
+                nbits: r.u32()?,
+            },
+            SET_TARGET => Self::SetTarget {
+                channel_id: r.u32()?,
+                maximum_target: r.u256()?,
+            },
+            SUBMIT_SHARES_STANDARD => Self::SubmitSharesStandard {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                job_id: r.u32()?,
+                nonce: r.u32()?,
+                ntime: r.u32()?,
+                version: r.u32()?,
+            },
+            SUBMIT_SHARES_SUCCESS => Self::SubmitSharesSuccess {
+                channel_id: r.u32()?,
+                last_sequence_number: r.u32()?,
+                new_submits_accepted_count: r.u32()?,
+                new_shares_sum: r.u64()?,
+            },
+            SUBMIT_SHARES_ERROR => Self::SubmitSharesError {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                error_code: r.str0_255()?,
+            },
+            RECONNECT => Self::Reconnect {
+                new_host: r.str0_255()?,
+                new_port: r.u16()?,
+            },
+            msg_type => Self::Unknown { extension_type, msg_type },
+        };
+        Ok(msg)
+    }
+}
+
+impl Frame {
+    // the header and the chunks of payload are encrypted one by one
+    pub fn encrypt(&self, cipher: &mut Cipher) -> Vec<u8> {
+        let mut header = self.extension_type.to_le_bytes().to_vec();
+        header.push(self.msg_type);
+        header.extend_from_slice(&(self.payload.len() as u32).to_le_bytes()[..3]);
+        cipher.encrypt(&[], &mut header);
+
+        for chunk in self.payload.chunks(MAX_CHUNK_SIZE - MAC_SIZE) {
+            let mut chunk = chunk.to_vec();
+            cipher.encrypt(&[], &mut chunk);
+            header.extend_from_slice(&chunk);
+        }
+        header
+    }
+}
+
+// the ciphers of the transport, (encryptor, decryptor)
+pub async fn handshake<A>(socket: &mut A, authority: schnorrsig::PublicKey) -> Result<(Cipher, Cipher)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+{
+    let (initiator, act1) = Initiator::new(authority);
+    socket.write_all(&act1).await?;
+    socket.flush().await?;
+
+    let mut act2 = [0u8; ACT2_SIZE];
+    socket.read_exact(&mut act2).await?;
+    initiator.finish(&act2)
+}
+
+pub async fn read_frame<R>(r: &mut R, cipher: &mut Cipher) -> Result<Frame>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = vec![0u8; HEADER_SIZE + MAC_SIZE];
+    r.read_exact(&mut header).await?;
+    cipher.decrypt(&[], &mut header)?;
+    let len = u32::from_le_bytes([header[3], header[4], header[5], 0]) as usize;
+
+    let chunks = (len + MAX_CHUNK_SIZE - MAC_SIZE - 1) / (MAX_CHUNK_SIZE - MAC_SIZE);
+    let mut encrypted = vec![0u8; len + chunks * MAC_SIZE];
+    r.read_exact(&mut encrypted).await?;
+    let mut payload = Vec::with_capacity(len);
+    for chunk in encrypted.chunks(MAX_CHUNK_SIZE) {
+        let mut chunk = chunk.to_vec();
+        cipher.decrypt(&[], &mut chunk)?;
+        payload.extend_from_slice(&chunk);
+    }
+
+    Ok(Frame {
+        extension_type: u16::from_le_bytes([header[0], header[1]]),
+        msg_type: header[2],
+        payload,
+    })
+}
+
+pub async fn write_frame<W>(w: &mut W, cipher: &mut Cipher, frame: &Frame) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    w.write_all(&frame.encrypt(cipher)).await?;
+    w.flush().await?;
+    Ok(())
+}
+
+// the message as the json of the handlers
+pub async fn read_json<R>(r: &mut R, cipher: &mut Cipher) -> Result<String>
+where
+    R: AsyncRead + Unpin,
+{
+    let msg = Message::decode(&read_frame(r, cipher).await?)?;
+    Ok(serde_json::to_string(&msg)?)
+}
+
+pub async fn write_json<W>(w: &mut W, cipher: &mut Cipher, json: &str) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let frame = serde_json::from_str::<Message>(json)?.encode()?;
+    write_frame(w, cipher, &frame).await
+}
+
+#[test]
+fn test_sv2_frame() {
+    let job = Message::NewMiningJob {
+        channel_id: 1,
+        job_id: 2,
+        min_ntime: None,
+        version: 0x20000000,
+        merkle_root: "11".repeat(32),
+    };
+    let frame = job.encode().unwrap();
+    assert_eq!(frame.payload.len(), 4 + 4 + 1 + 4 + 1 + 32);
+    assert_eq!((frame.extension_type, Message::decode(&frame).unwrap()), (CHANNEL_BIT, job));
+    // the layout of the spec: channel_id, job_id, OPTION[u32] min_ntime, version, B0_32 merkle_root
+    let payload = hex::decode(concat!(
+        "05000000",
+        "07000000",
+        "01",
+        "f1536565",
+        "00000020",
+        "20",
+        "3ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a"
+    ))
+    .unwrap();
+    let frame = Frame {
+        extension_type: CHANNEL_BIT,
+        msg_type: NEW_MINING_JOB,
+        payload,
+    };
+    assert_eq!(
+        Message::decode(&frame).unwrap(),
+        Message::NewMiningJob {
+            channel_id: 5,
+            job_id: 7,
+            min_ntime: Some(0x656553f1),
+            version: 0x20000000,
+            merkle_root: "3ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a".to_owned(),
+        }
+    );
+    assert_eq!(Message::decode(&frame).unwrap().encode().unwrap(), frame);
+    assert!(Message::decode(&Frame { payload: vec![0; 3], ..frame }).is_err());
+    let unknown = Frame {
+        extension_type: 1,
+        msg_type: 0,
+        payload: vec![],
+    };
+    assert!(matches!(Message::decode(&unknown).unwrap(), Message::Unknown { extension_type: 1, .. }));
+    let json = serde_json::to_string(&Message::SetTarget {
+        channel_id: 1,
+        maximum_target: "ff".repeat(32),
+    })
+    .unwrap();
+    assert!(json.starts_with(r#"{"SetTarget":{"channel_id":1,"#), "{}", json);
+
+    // the payload larger than a chunk is echoed by the pool
+    let big = Frame {
+        extension_type: 0,
+        msg_type: 0x70,
+        payload: (0..70000).map(|i| i as u8).collect(),
+    };
+    let responder = noise::Responder::new(&[9; 32]);
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let (mut pool, mut miner) = tokio::net::UnixStream::pair().unwrap();
+        let pool = async {
+            let mut act1 = [0u8; ellswift::ELLSWIFT_SIZE];
+            pool.read_exact(&mut act1).await.unwrap();
+            let (act2, mut encryptor, mut decryptor) = responder.accept(&act1).unwrap();
+            pool.write_all(&act2).await.unwrap();
+            let frame = read_frame(&mut pool, &mut decryptor).await.unwrap();
+            write_frame(&mut pool, &mut encryptor, &frame).await.unwrap();
+            frame
+        };
+        let miner = async {
+            let (mut encryptor, mut decryptor) = handshake(&mut miner, responder.authority_key()).await.unwrap();
+            write_frame(&mut miner, &mut encryptor, &big).await.unwrap();
+            read_frame(&mut miner, &mut decryptor).await.unwrap()
+        };
+        assert_eq!(futures::future::join(pool, miner).await, (big.clone(), big.clone()));
+    });
+}
@@ -0,0 +1,328 @@
+// the Noise_NX handshake of stratum v2 and the ciphers of the transport,
+// the pool is authenticated by the certificate of its static key signed by the authority key of the pool url
+use bitcoin::secp256k1::{schnorrsig, Message, Secp256k1, SecretKey};
+use bitcoin::util::base58;
+use bitcoin_hashes::hmac::{Hmac, HmacEngine};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::ellswift::{self, ELLSWIFT_SIZE};
+use crate::util::Result;
+
+const PROTOCOL_NAME: &[u8] = b"Noise_NX_Secp256k1+EllSwift_ChaChaPoly_SHA256";
+pub const MAC_SIZE: usize = 16;
+// version(2), valid_from(4), not_valid_after(4) and the schnorr signature(64)
+const CERTIFICATE_SIZE: usize = 74;
+// the ephemeral key, the encrypted static key and the encrypted certificate of the responder
+pub const ACT2_SIZE: usize = ELLSWIFT_SIZE + ELLSWIFT_SIZE + MAC_SIZE + CERTIFICATE_SIZE + MAC_SIZE;
+// the version bytes before the x-only key of the base58check authority key
+const AUTHORITY_KEY_VERSION: [u8; 2] = [1, 0];
+
+// the x-only public key in base58check, such as the path of stratum2+tcp://pool.example.com:3336/9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72
+pub fn parse_authority_key(key: &str) -> Result<schnorrsig::PublicKey> {
+    let bytes = base58::from_check(key).map_err(|e| format_err!("invalid authority key {}: {}", key, e))?;
+    match bytes.split_at(AUTHORITY_KEY_VERSION.len().min(bytes.len())) {
+        (version, key) if version == AUTHORITY_KEY_VERSION => Ok(schnorrsig::PublicKey::from_slice(key)?),
+        _ => Err(format_err!("invalid authority key {}: unknown version", key)),
+    }
+}
+
+pub fn authority_key_string(key: &schnorrsig::PublicKey) -> String {
+    let mut bytes = AUTHORITY_KEY_VERSION.to_vec();
+    bytes.extend_from_slice(&key.serialize());
+    base58::check_encode_slice(&bytes)
+}
+
+// ChaCha20-Poly1305 with the nonce of the 4 zero bytes and the little endian counter
+pub struct Cipher {
+    key: LessSafeKey,
+    n: u64,
+}
+
+impl Cipher {
+    fn new(k: &[u8; 32]) -> Self {
+        Self {
+            key: LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, k).expect("32 bytes key")),
+            n: 0,
+        }
+    }
+    fn nonce(&mut self) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.n.to_le_bytes());
+        self.n += 1;
+        Nonce::assume_unique_for_key(nonce)
+    }
+    // the mac is appended
+    pub fn encrypt(&mut self, ad: &[u8], data: &mut Vec<u8>) {
+        let nonce = self.nonce();
+        self.key.seal_in_place_append_tag(nonce, Aad::from(ad), data).expect("chacha20poly1305 seal");
+    }
+    pub fn decrypt(&mut self, ad: &[u8], data: &mut Vec<u8>) -> Result<()> {
+        let nonce = self.nonce();
+        let len = self.key.open_in_place(nonce, Aad::from(ad), data).map_err(|_| format_err!("noise decrypt failed"))?.len();
+        data.truncate(len);
+        Ok(())
+    }
+}
+
+// the chaining key, the handshake hash and the cipher of the handshake
+struct Symmetric {
+    ck: [u8; 32],
+    h: [u8; 32],
+    k: Option<Cipher>,
+}
+
+impl Symmetric {
+    // the prologue is empty, but it is still mixed
+    fn new() -> Self {
+        let ck = sha256::Hash::hash(PROTOCOL_NAME).into_inner();
+        let mut this = Self { ck, h: ck, k: None };
+        this.mix_hash(&[]);
+        this
+    }
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.h);
+        engine.input(data);
+        self.h = sha256::Hash::from_engine(engine).into_inner();
+    }
+    fn mix_key(&mut self, ikm: &[u8]) {
+        let (ck, k) = hkdf2(&self.ck, ikm);
+        self.ck = ck;
+        self.k = Some(Cipher::new(&k));
+    }
+    // the plaintext is only hashed before the first mix_key
+    fn encrypt_and_hash(&mut self, data: &mut Vec<u8>) {
+        if let Some(k) = self.k.as_mut() {
+            k.encrypt(&self.h, data);
+        }
+        let h = data.clone();
+        self.mix_hash(&h);
+    }
+    fn decrypt_and_hash(&mut self, data: &mut Vec<u8>) -> Result<()> {
+        let h = data.clone();
+        if let Some(k) = self.k.as_mut() {
+            k.decrypt(&self.h, data)?;
+        }
+        self.mix_hash(&h);
+        Ok(())
+    }
+    // (the cipher of the initiator to the responder, the one of the responder to the initiator)
+    fn split(&self) -> (Cipher, Cipher) {
+        let (k1, k2) = hkdf2(&self.ck, &[]);
+        (Cipher::new(&k1), Cipher::new(&k2))
+    }
+}
+
+fn hmac(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    data.iter().for_each(|d| engine.input(d));
+    Hmac::from_engine(engine).into_inner()
+}
+
+fn hkdf2(ck: &[u8; 32], ikm: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let temp = hmac(ck, &[ikm]);
+    let out1 = hmac(&temp, &[&[1]]);
+    let out2 = hmac(&temp, &[&out1, &[2]]);
+    (out1, out2)
+}
+
+fn now_secs() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or_default()
+}
+
+// the message signed by the authority: version, valid_from, not_valid_after and the x-only static key
+fn certificate_message(certificate: &[u8], static_key: &[u8; 32]) -> Message {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&certificate[..10]);
+    engine.input(static_key);
+    Message::from_slice(&sha256::Hash::from_engine(engine)).expect("32 bytes hash")
+}
+
+pub struct Initiator {
+    symmetric: Symmetric,
+    e: SecretKey,
+    ell: [u8; ELLSWIFT_SIZE],
+    authority: schnorrsig::PublicKey,
+}
+
+impl Initiator {
+    // the act 1 is the ephemeral key with the empty payload
+    pub fn new(authority: schnorrsig::PublicKey) -> (Self, [u8; ELLSWIFT_SIZE]) {
+        let (e, ell) = ellswift::generate();
+        Self::with_ephemeral(authority, e, ell)
+    }
+    fn with_ephemeral(authority: schnorrsig::PublicKey, e: SecretKey, ell: [u8; ELLSWIFT_SIZE]) -> (Self, [u8; ELLSWIFT_SIZE]) {
+        let mut symmetric = Symmetric::new();
+        symmetric.mix_hash(&ell);
+        symmetric.encrypt_and_hash(&mut vec![]);
+
+        (Self { symmetric, e, ell, authority }, ell)
+    }
+    // (encryptor, decryptor) of the transport once the certificate of the act 2 is verified
+    pub fn finish(mut self, act2: &[u8]) -> Result<(Cipher, Cipher)> {
+        if act2.len() != ACT2_SIZE {
+            return Err(format_err!("invalid noise handshake size: {}", act2.len()));
+        }
+        let s = &mut self.symmetric;
+        let re: [u8; ELLSWIFT_SIZE] = act2[..ELLSWIFT_SIZE].try_into().unwrap();
+        s.mix_hash(&re);
+        s.mix_key(&ellswift::xdh(&self.ell, &re, &self.e, true));
+
+        let mut rs = act2[ELLSWIFT_SIZE..ELLSWIFT_SIZE * 2 + MAC_SIZE].to_vec();
+        s.decrypt_and_hash(&mut rs)?;
+        let rs: [u8; ELLSWIFT_SIZE] = rs.as_slice().try_into().unwrap();
+        s.mix_key(&ellswift::xdh(&self.ell, &rs, &self.e, true));
+
+        let mut certificate = act2[ELLSWIFT_SIZE * 2 + MAC_SIZE..].to_vec();
+        s.decrypt_and_hash(&mut certificate)?;
+        let version = u16::from_le_bytes(certificate[..2].try_into().unwrap());
+        let valid_from = u32::from_le_bytes(certificate[2..6].try_into().unwrap());
+        let not_valid_after = u32::from_le_bytes(certificate[6..10].try_into().unwrap());
+        let now = now_secs();
+        if version != 0 || now < valid_from || now > not_valid_after {
+            return Err(format_err!("invalid certificate of pool: version {}, valid {}..{}, now {}", version, valid_from, not_valid_after, now));
+        }
+
+        let signature = schnorrsig::Signature::from_slice(&certificate[10..])?;
+        let message = certificate_message(&certificate, &ellswift::decode(&rs));
+        let authority = self.authority;
+        Secp256k1::new()
+            .schnorrsig_verify(&signature, &message, &authority)
+            .map_err(|e| format_err!("the certificate of pool is not signed by the authority key {}: {}", authority_key_string(&authority), e))?;
+
+        Ok(s.split())
+    }
+}
+
+// the pool side, only for the tests
+#[cfg(test)]
+pub struct Responder {
+    pub authority: schnorrsig::KeyPair,
+    pub valid: (u32, u32),
+}
+
+#[cfg(test)]
+impl Responder {
+    pub fn new(authority: &[u8; 32]) -> Self {
+        let now = now_secs();
+        Self {
+            authority: schnorrsig::KeyPair::from_seckey_slice(&Secp256k1::signing_only(), authority).unwrap(),
+            valid: (now - 3600, now + 3600),
+        }
+    }
+    pub fn authority_key(&self) -> schnorrsig::PublicKey {
+        schnorrsig::PublicKey::from_keypair(&Secp256k1::signing_only(), &self.authority)
+    }
+    // (act 2, encryptor, decryptor)
+    pub fn accept(&self, act1: &[u8]) -> Result<(Vec<u8>, Cipher, Cipher)> {
+        let re: [u8; ELLSWIFT_SIZE] = act1.try_into()?;
+        let mut s = Symmetric::new();
+        s.mix_hash(&re);
+        s.decrypt_and_hash(&mut vec![])?;
+
+        let (e, ell) = ellswift::generate();
+        let mut act2 = ell.to_vec();
+        s.mix_hash(&ell);
+        s.mix_key(&ellswift::xdh(&re, &ell, &e, false));
+
+        let (static_key, static_ell) = ellswift::generate();
+        let mut encrypted = static_ell.to_vec();
+        s.encrypt_and_hash(&mut encrypted);
+        act2.extend_from_slice(&encrypted);
+        s.mix_key(&ellswift::xdh(&re, &static_ell, &static_key, false));
+
+        let mut certificate = 0u16.to_le_bytes().to_vec();
+        certificate.extend_from_slice(&self.valid.0.to_le_bytes());
+        certificate.extend_from_slice(&self.valid.1.to_le_bytes());
+        let message = certificate_message(&certificate, &ellswift::decode(&static_ell));
+        let signature = Secp256k1::signing_only().schnorrsig_sign_no_aux_rand(&message, &self.authority);
+        certificate.extend_from_slice(signature.as_ref());
+        s.encrypt_and_hash(&mut certificate);
+        act2.extend_from_slice(&certificate);
+
+        let (c1, c2) = s.split();
+        Ok((act2, c2, c1))
+    }
+}
+
+#[test]
+fn test_noise_handshake() {
+    let responder = Responder::new(&[7; 32]);
+    let key = authority_key_string(&responder.authority_key());
+    assert_eq!(parse_authority_key(&key).unwrap(), responder.authority_key());
+    assert!(parse_authority_key(&base58::check_encode_slice(&responder.authority_key().serialize())).is_err());
+
+    let (initiator, act1) = Initiator::new(parse_authority_key(&key).unwrap());
+    let (act2, mut pool_encryptor, mut pool_decryptor) = responder.accept(&act1).unwrap();
+    assert_eq!(act2.len(), ACT2_SIZE);
+    let (mut encryptor, mut decryptor) = initiator.finish(&act2).unwrap();
+
+    for i in 0..3u8 {
+        let mut data = vec![i; 6];
+        encryptor.encrypt(&[], &mut data);
+        assert_eq!(data.len(), 6 + MAC_SIZE);
+        pool_decryptor.decrypt(&[], &mut data).unwrap();
+        assert_eq!(data, [i; 6]);
+
+        pool_encryptor.encrypt(&[], &mut data);
+        decryptor.decrypt(&[], &mut data).unwrap();
+        assert_eq!(data, [i; 6]);
+    }
+    // the nonces are out of sync
+    encryptor.encrypt(&[], &mut vec![]);
+    let mut data = vec![0; 6];
+    encryptor.encrypt(&[], &mut data);
+    assert!(pool_decryptor.decrypt(&[], &mut data).is_err());
+    assert!(parse_authority_key("9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72").is_ok());
+
+    // signed by another authority, or expired
+    let (initiator, act1) = Initiator::new(Responder::new(&[8; 32]).authority_key());
+    assert!(initiator.finish(&responder.accept(&act1).unwrap().0).is_err());
+    let mut expired = Responder::new(&[7; 32]);
+    expired.valid.1 = expired.valid.0 + 1;
+    let (initiator, act1) = Initiator::new(expired.authority_key());
+    assert!(initiator.finish(&expired.accept(&act1).unwrap().0).is_err());
+}
+
+// a handshake recorded with the responder of SRI(noise_sv2 1.4.2 of https://github.com/stratum-mining/stratum):
+// Responder::new_with_rng by the authority key [7; 32] with the cert_validity u32::MAX and StdRng::seed_from_u64(324),
+// then step_1_with_now_rng(act 1, 1700000000), it encrypts "pool" and decrypts "cminer" from us
+#[test]
+fn test_noise_vector() {
+    let e = SecretKey::from_slice(&[0x11; 32]).unwrap();
+    let mut ell = [0u8; ELLSWIFT_SIZE];
+    hex::decode_to_slice(
+        "e8902c7331527e223bf3d97d077ed97fcd7ac3d38f289b5b3ba4d97ea510db0584302c90247da55fa91a1622bd089f921ea85180a7b9a90e56ccb6b47b34c04b",
+        &mut ell,
+    )
+    .unwrap();
+    let authority = Responder::new(&[7; 32]).authority_key();
+    assert_eq!(hex::encode(authority.serialize()), "989c0b76cb563971fdc9bef31ec06c3560f3249d6ee9e5d83c57625596e05f6f");
+    let act2 = hex::decode(concat!(
+        "51633b97bdabed563cea0e897bbf23b4c82d9d800a3974fd3ba1718a63dd262e3b134b10997b7468ecdfbcf9329faac6e83d2bad2ad9f09e06e1704bcf086012",
+        "1bdd22d778503ff3cbbe121adc0750188a66cb137826c9d506718900a827a7efab2f61823f24250d7798079b374ff21244d0ee049550a3238acb3e67a0dd10fa",
+        "60b7982a510b70364be594b0db0b0f8e475b8e2e81bec40cca7d8644a858ad8265827529c5d18c4d8e20084008470f4caf2c132f683310dcce817ec9e3cff5a0",
+        "c512157514894f8538504f99ba015da585bc64545f12a3a44ed8b33e3f7182f1f1ee01a34bfe73eb60ed"
+    ))
+    .unwrap();
+
+    let (initiator, act1) = Initiator::with_ephemeral(authority, e, ell);
+    assert_eq!(act1, ell);
+    let (mut encryptor, mut decryptor) = initiator.finish(&act2).unwrap();
+
+    let mut data = b"cminer".to_vec();
+    encryptor.encrypt(&[], &mut data);
+    assert_eq!(hex::encode(&data), "4ac38ebbbecf28289c98b82ae1839b037e2a1cf44ee0");
+    let mut data = hex::decode("09ed821bafc3676482d675f058af416d5214207a").unwrap();
+    decryptor.decrypt(&[], &mut data).unwrap();
+    assert_eq!(data, b"pool");
+
+    // any flipped bit of the act 2 fails the mac
+    let mut act2 = act2;
+    act2[ELLSWIFT_SIZE + 1] ^= 1;
+    assert!(Initiator::with_ephemeral(authority, e, ell).0.finish(&act2).is_err());
+}
@@ -1,4 +1,7 @@
-use std::{fmt, time::Instant};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
 
 pub type Error = anyhow::Error;
 pub type Result<T> = anyhow::Result<T>;
@@ -30,6 +33,25 @@ impl AsRef<str> for DescError {
     }
 }
 
+// the pool rejected the login, reconnecting with the same user won't help
+#[derive(Debug, Error)]
+#[error("{method} {user} rejected by the pool, error: {error}")]
+pub struct LoginError {
+    pub method: &'static str,
+    pub user: String,
+    pub error: String,
+}
+
+impl LoginError {
+    pub fn new(method: &'static str, pool: &crate::config::Pool, error: Option<String>) -> Self {
+        Self {
+            method,
+            user: format!("{}.{}", pool.user, pool.rig),
+            error: error.unwrap_or_else(|| "null".to_owned()),
+        }
+    }
+}
+
 pub fn clean_0x(s: &str) -> &str {
     if s.starts_with("0x") {
         &s[2..]
@@ -74,6 +96,33 @@ pub fn sleep_secs(secs: u64) {
     thread::sleep(Duration::from_secs(secs))
 }
 
+// exponential backoff with the equal jitter: the delay is random in [d/2, d], d doubles until the max
+#[derive(Debug)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        let max = max.max(min);
+        Self { min, max, current: min }
+    }
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+    pub fn next_delay(&mut self) -> Duration {
+        use rand::{thread_rng, Rng};
+
+        let d = self.current;
+        self.current = (self.current * 2).min(self.max);
+
+        let half = d / 2;
+        half + thread_rng().gen_range(Duration::default(), d - half + Duration::from_nanos(1))
+    }
+}
+
 pub static EXITED: AtomicBool = AtomicBool::new(false);
 
 pub fn catch_ctrlc() {
@@ -87,3 +136,14 @@ pub fn catch_ctrlc() {
 pub fn exited() -> bool {
     EXITED.load(Ordering::Relaxed)
 }
+
+#[test]
+fn test_backoff() {
+    let mut b = Backoff::new(Duration::from_secs(4), Duration::from_secs(20));
+    for max in [4, 8, 16, 20, 20] {
+        let d = b.next_delay();
+        assert!(d >= Duration::from_secs(max) / 2 && d <= Duration::from_secs(max), "{:?} of {}", d, max);
+    }
+    b.reset();
+    assert!(b.next_delay() <= Duration::from_secs(4));
+}